@@ -0,0 +1,167 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use image::imageops::FilterType;
+use image::{DynamicImage, GrayImage, Luma, Rgb, RgbImage};
+use ndarray::Array2;
+use rmbg_core::processor::BackgroundRemover;
+
+// 4K UHD and 8K UHD frames
+const SIZES: [(&str, u32, u32); 2] = [("4k", 3840, 2160), ("8k", 7680, 4320)];
+
+// Smooth gradients with some texture, so resampling has real work to do
+fn test_image(width: u32, height: u32) -> DynamicImage {
+    DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+        Rgb([
+            (x * 255 / width) as u8,
+            (y * 255 / height) as u8,
+            ((x ^ y) & 0xff) as u8,
+        ])
+    }))
+}
+
+fn test_mask(width: u32, height: u32) -> GrayImage {
+    let (cx, cy) = (width as f32 / 2.0, height as f32 / 2.0);
+    GrayImage::from_fn(width, height, |x, y| {
+        let distance = ((x as f32 - cx).powi(2) + (y as f32 - cy).powi(2)).sqrt();
+        Luma([(255.0 - distance / cx * 255.0).clamp(0.0, 255.0) as u8])
+    })
+}
+
+fn test_output() -> Array2<f32> {
+    Array2::from_shape_fn((320, 320), |(y, x)| {
+        ((x as f32 / 20.0).sin() * (y as f32 / 30.0).cos() + 1.0) * 4.0 - 2.0
+    })
+}
+
+// The per-pixel implementations these steps had before they were rewritten
+// on slices, kept to measure the rewrite against
+mod baseline {
+    use image::{DynamicImage, GrayImage, ImageBuffer, Luma, Rgba, RgbaImage};
+    use ndarray::{Array4, ArrayView2};
+
+    pub fn preprocess_image(image: &DynamicImage) -> Array4<f32> {
+        let resized = image
+            .resize_exact(320, 320, image::imageops::FilterType::Lanczos3)
+            .to_rgb8();
+
+        let mut input = Array4::<f32>::zeros((1, 3, 320, 320));
+        for (x, y, pixel) in resized.enumerate_pixels() {
+            let (x, y) = (x as usize, y as usize);
+            input[[0, 0, y, x]] = pixel[0] as f32 / 255.0;
+            input[[0, 1, y, x]] = pixel[1] as f32 / 255.0;
+            input[[0, 2, y, x]] = pixel[2] as f32 / 255.0;
+        }
+        input
+    }
+
+    pub fn postprocess_output(
+        channel_data: ArrayView2<f32>,
+        (min, max): (f32, f32),
+        target_width: u32,
+        target_height: u32,
+        filter: image::imageops::FilterType,
+    ) -> GrayImage {
+        let range = max - min;
+        let (height, width) = channel_data.dim();
+
+        let mut mask_img = ImageBuffer::new(width as u32, height as u32);
+        for y in 0..height {
+            for x in 0..width {
+                let val = channel_data[[y, x]];
+                let normalized = if range > 0.0 {
+                    ((val - min) / range * 255.0).clamp(0.0, 255.0) as u8
+                } else {
+                    0
+                };
+                mask_img.put_pixel(x as u32, y as u32, Luma([normalized]));
+            }
+        }
+
+        image::imageops::resize(&mask_img, target_width, target_height, filter)
+    }
+
+    pub fn apply_mask(image: &DynamicImage, mask: &GrayImage) -> DynamicImage {
+        let rgba = image.to_rgba8();
+        let (width, height) = rgba.dimensions();
+
+        let mut result = RgbaImage::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let pixel = rgba.get_pixel(x, y);
+                let mask_value = mask.get_pixel(x, y)[0];
+                result.put_pixel(x, y, Rgba([pixel[0], pixel[1], pixel[2], mask_value]));
+            }
+        }
+
+        DynamicImage::ImageRgba8(result)
+    }
+}
+
+fn preprocess(c: &mut Criterion) {
+    let mut group = c.benchmark_group("preprocess_image");
+    group.sample_size(10);
+    for (name, width, height) in SIZES {
+        let image = test_image(width, height);
+        group.bench_with_input(BenchmarkId::new("baseline", name), &image, |b, image| {
+            b.iter(|| baseline::preprocess_image(image))
+        });
+        group.bench_with_input(BenchmarkId::new("current", name), &image, |b, image| {
+            b.iter(|| BackgroundRemover::preprocess_image(image, 320))
+        });
+    }
+    group.finish();
+}
+
+fn postprocess(c: &mut Criterion) {
+    let mut group = c.benchmark_group("postprocess_output");
+    group.sample_size(10);
+    let output = test_output();
+    for (name, width, height) in SIZES {
+        group.bench_function(BenchmarkId::new("baseline", name), |b| {
+            b.iter(|| {
+                baseline::postprocess_output(
+                    output.view(),
+                    (-2.0, 6.0),
+                    width,
+                    height,
+                    FilterType::Lanczos3,
+                )
+            })
+        });
+        group.bench_function(BenchmarkId::new("current", name), |b| {
+            b.iter(|| {
+                BackgroundRemover::postprocess_output(
+                    output.view(),
+                    (-2.0, 6.0),
+                    width,
+                    height,
+                    FilterType::Lanczos3,
+                )
+            })
+        });
+    }
+    group.finish();
+}
+
+fn apply_mask(c: &mut Criterion) {
+    let mut group = c.benchmark_group("apply_mask");
+    group.sample_size(10);
+    for (name, width, height) in SIZES {
+        let image = test_image(width, height);
+        let mask = test_mask(width, height);
+        let input = (image, mask);
+        group.bench_with_input(
+            BenchmarkId::new("baseline", name),
+            &input,
+            |b, (image, mask)| b.iter(|| baseline::apply_mask(image, mask)),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("current", name),
+            &input,
+            |b, (image, mask)| b.iter(|| BackgroundRemover::apply_mask(image, mask)),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, preprocess, postprocess, apply_mask);
+criterion_main!(benches);
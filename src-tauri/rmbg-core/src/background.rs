@@ -0,0 +1,54 @@
+use image::{imageops, ImageBuffer, Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+use crate::effects::{self, Effect};
+use crate::processor;
+use crate::Result;
+
+// What to place behind the subject instead of transparency
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Background {
+    // Solid RGBA color
+    Color([u8; 4]),
+    // Image file, scaled and center cropped to cover the output
+    Image(PathBuf),
+}
+
+// Parses a color given as `r,g,b` or `r,g,b,a` like rembg takes it, alpha
+// defaults to opaque
+pub fn parse_color(value: &str) -> Option<Rgba<u8>> {
+    let channels = value
+        .trim_matches(|c| c == '(' || c == ')' || c == '[' || c == ']')
+        .split(',')
+        .map(|c| c.trim().parse::<u8>().ok())
+        .collect::<Option<Vec<_>>>()?;
+
+    match channels.as_slice() {
+        [r, g, b] => Some(Rgba([*r, *g, *b, 255])),
+        [r, g, b, a] => Some(Rgba([*r, *g, *b, *a])),
+        _ => None,
+    }
+}
+
+// Places a cutout on a background, drawing the effects between the two
+pub fn compose(
+    subject: &RgbaImage,
+    background: &Background,
+    effects: &[Effect],
+) -> Result<RgbaImage> {
+    let (width, height) = subject.dimensions();
+
+    let mut canvas = match background {
+        Background::Color(color) => ImageBuffer::from_pixel(width, height, Rgba(*color)),
+        Background::Image(path) => processor::open_image(path)?
+            .resize_to_fill(width, height, imageops::FilterType::Lanczos3)
+            .to_rgba8(),
+    };
+
+    effects::render(&mut canvas, subject, effects);
+    imageops::overlay(&mut canvas, subject, 0, 0);
+
+    Ok(canvas)
+}
@@ -0,0 +1,178 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::dataset::GroundTruth;
+use crate::metrics;
+use crate::models::{self, Precision};
+use crate::processor::{self, BackgroundRemover, InferenceOptions};
+use crate::session::{Provider, SessionOptions};
+use crate::{Error, Result};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BenchmarkOptions {
+    pub precision: Precision,
+    pub session: SessionOptions,
+    pub inference: InferenceOptions,
+    // Folder of ground truth masks named like the images, to report how
+    // well each model does besides how fast
+    pub ground_truth_dir: Option<PathBuf>,
+}
+
+impl BenchmarkOptions {
+    pub fn validate(&self) -> Result<()> {
+        self.session.validate()?;
+        self.inference.validate()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelBenchmark {
+    pub model_id: String,
+    pub precision: Precision,
+    pub provider: Provider,
+    // Time to load the model and set up the session
+    pub load_time_ms: f64,
+    // The first run, which is slower while ONNX Runtime allocates and
+    // optimizes. Not included in the latencies.
+    pub warmup_ms: f64,
+    pub mean_latency_ms: f64,
+    pub median_latency_ms: f64,
+    // Peak resident memory of the process while the model was loaded and
+    // run. Only reported on Linux.
+    pub peak_memory_mb: Option<f64>,
+    // Mean IoU over the images that have a ground truth mask
+    pub mean_iou: Option<f64>,
+    pub images: Vec<ImageBenchmark>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageBenchmark {
+    pub path: PathBuf,
+    // Mask prediction, from the decoded image to the full resolution mask
+    pub latency_ms: f64,
+    pub iou: Option<f64>,
+}
+
+// Loads a model and predicts a mask for each image, timing every step
+pub fn benchmark_model(
+    model_id: &str,
+    images: &[PathBuf],
+    options: &BenchmarkOptions,
+) -> Result<ModelBenchmark> {
+    options.validate()?;
+
+    let ground_truth = options
+        .ground_truth_dir
+        .as_deref()
+        .map(GroundTruth::open)
+        .transpose()?;
+    let model_path = models::get_variant_path(model_id, options.precision)?;
+
+    memory::reset_peak();
+
+    let start = Instant::now();
+    let mut remover = BackgroundRemover::with_options(&model_path, &options.session)?;
+    let load_time_ms = elapsed_ms(start);
+
+    let mut warmup_ms = 0.0;
+    let mut results = Vec::with_capacity(images.len());
+    for (index, path) in images.iter().enumerate() {
+        let image = read_image(path)?;
+
+        if index == 0 {
+            let start = Instant::now();
+            remover.predict_mask_with_options(&image, &options.inference)?;
+            warmup_ms = elapsed_ms(start);
+        }
+
+        let start = Instant::now();
+        let mask = remover.predict_mask_with_options(&image, &options.inference)?;
+        let latency_ms = elapsed_ms(start);
+
+        let iou = match &ground_truth {
+            Some(ground_truth) => ground_truth
+                .mask_for(path, mask.width(), mask.height())?
+                .map(|truth| metrics::iou(&mask, &truth)),
+            None => None,
+        };
+
+        results.push(ImageBenchmark {
+            path: path.clone(),
+            latency_ms,
+            iou,
+        });
+    }
+
+    let mut latencies: Vec<f64> = results.iter().map(|image| image.latency_ms).collect();
+    let ious: Vec<f64> = results.iter().filter_map(|image| image.iou).collect();
+
+    Ok(ModelBenchmark {
+        model_id: model_id.to_string(),
+        precision: options.precision,
+        provider: remover.provider(),
+        load_time_ms,
+        warmup_ms,
+        mean_latency_ms: mean(&latencies).unwrap_or(0.0),
+        median_latency_ms: median(&mut latencies).unwrap_or(0.0),
+        peak_memory_mb: memory::peak_bytes().map(|bytes| bytes as f64 / (1024.0 * 1024.0)),
+        mean_iou: mean(&ious),
+        images: results,
+    })
+}
+
+fn read_image(path: &Path) -> Result<image::DynamicImage> {
+    let bytes = std::fs::read(path).map_err(Error::file(path))?;
+    processor::decode_image(&bytes)
+}
+
+fn elapsed_ms(start: Instant) -> f64 {
+    start.elapsed().as_secs_f64() * 1000.0
+}
+
+fn mean(values: &[f64]) -> Option<f64> {
+    if values.is_empty() {
+        None
+    } else {
+        Some(values.iter().sum::<f64>() / values.len() as f64)
+    }
+}
+
+fn median(values: &mut [f64]) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(f64::total_cmp);
+    let middle = values.len() / 2;
+    Some(if values.len() % 2 == 1 {
+        values[middle]
+    } else {
+        (values[middle - 1] + values[middle]) / 2.0
+    })
+}
+
+// Peak resident memory from the kernel's high water mark. Resetting it lets
+// each model be measured on its own within one process.
+#[cfg(target_os = "linux")]
+mod memory {
+    pub fn reset_peak() {
+        let _ = std::fs::write("/proc/self/clear_refs", "5");
+    }
+
+    pub fn peak_bytes() -> Option<u64> {
+        let status = std::fs::read_to_string("/proc/self/status").ok()?;
+        let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+        let kilobytes: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+        Some(kilobytes * 1024)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod memory {
+    pub fn reset_peak() {}
+
+    pub fn peak_bytes() -> Option<u64> {
+        None
+    }
+}
@@ -0,0 +1,113 @@
+// Mean over a square window of pixels with N channels, done as a horizontal
+// then a vertical running sum. Windows are clipped at the image border.
+pub(crate) fn box_blur<const N: usize>(
+    values: &[[f32; N]],
+    width: usize,
+    height: usize,
+    radius: usize,
+) -> Vec<[f32; N]> {
+    let mut horizontal = vec![[0.0; N]; values.len()];
+    for y in 0..height {
+        let row = &values[y * width..(y + 1) * width];
+        blur_line(row, &mut horizontal[y * width..(y + 1) * width], radius);
+    }
+
+    let mut output = vec![[0.0; N]; values.len()];
+    let mut column = vec![[0.0; N]; height];
+    let mut blurred = vec![[0.0; N]; height];
+    for x in 0..width {
+        for y in 0..height {
+            column[y] = horizontal[y * width + x];
+        }
+        blur_line(&column, &mut blurred, radius);
+        for y in 0..height {
+            output[y * width + x] = blurred[y];
+        }
+    }
+
+    output
+}
+
+// Three box blurs in a row come close to a Gaussian with a sigma of about
+// the box radius
+pub(crate) fn gaussian_blur<const N: usize>(
+    values: &[[f32; N]],
+    width: usize,
+    height: usize,
+    sigma: usize,
+) -> Vec<[f32; N]> {
+    let once = box_blur(values, width, height, sigma);
+    let twice = box_blur(&once, width, height, sigma);
+    box_blur(&twice, width, height, sigma)
+}
+
+// Mean over a disc, the shape of a camera lens' out of focus blur. Each row
+// of the disc is summed from per-row prefix sums.
+pub(crate) fn disc_blur<const N: usize>(
+    values: &[[f32; N]],
+    width: usize,
+    height: usize,
+    radius: usize,
+) -> Vec<[f32; N]> {
+    let stride = width + 1;
+    let mut prefix = vec![[0.0; N]; stride * height];
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = prefix[y * stride + x];
+            add(&mut sum, &values[y * width + x], 1.0);
+            prefix[y * stride + x + 1] = sum;
+        }
+    }
+
+    // Half width of the disc's row at each vertical distance from its center
+    let spans: Vec<usize> = (0..=radius)
+        .map(|dy| ((radius * radius - dy * dy) as f32).sqrt() as usize)
+        .collect();
+
+    let mut output = vec![[0.0; N]; values.len()];
+    for y in 0..height {
+        let rows = y.saturating_sub(radius)..(y + radius + 1).min(height);
+        for x in 0..width {
+            let mut sum = [0.0; N];
+            let mut count = 0;
+            for row in rows.clone() {
+                let span = spans[row.abs_diff(y)];
+                let start = x.saturating_sub(span);
+                let end = (x + span + 1).min(width);
+                add(&mut sum, &prefix[row * stride + end], 1.0);
+                add(&mut sum, &prefix[row * stride + start], -1.0);
+                count += end - start;
+            }
+            output[y * width + x] = sum.map(|s| s / count as f32);
+        }
+    }
+
+    output
+}
+
+fn blur_line<const N: usize>(line: &[[f32; N]], output: &mut [[f32; N]], radius: usize) {
+    let len = line.len();
+    let mut sum = [0.0f32; N];
+    for value in &line[..radius.min(len)] {
+        add(&mut sum, value, 1.0);
+    }
+
+    for (i, out) in output.iter_mut().enumerate() {
+        // Slide the window [i - radius, i + radius] one step right
+        if i + radius < len {
+            add(&mut sum, &line[i + radius], 1.0);
+        }
+        if i > radius {
+            add(&mut sum, &line[i - radius - 1], -1.0);
+        }
+
+        let count = (i + radius).min(len - 1) + 1 - i.saturating_sub(radius);
+        *out = sum.map(|s| s / count as f32);
+    }
+}
+
+fn add<const N: usize>(sum: &mut [f32; N], value: &[f32; N], sign: f32) {
+    for (s, v) in sum.iter_mut().zip(value) {
+        *s += v * sign;
+    }
+}
@@ -0,0 +1,298 @@
+use image::{imageops, ImageBuffer, Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+use crate::crop;
+use crate::{Error, Result};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Alignment {
+    #[default]
+    Center,
+    // Rests the subject on the bottom margin, for products photographed
+    // standing on a surface
+    Bottom,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CanvasOptions {
+    pub width: u32,
+    pub height: u32,
+    // Fraction of the canvas the subject's bounding box may take up in
+    // either direction
+    pub fill_ratio: f32,
+    pub alignment: Alignment,
+    // Solid RGBA color behind the subject, transparent when unset
+    pub background: Option<[u8; 4]>,
+    // Pixels with alpha above this count as part of the subject
+    pub alpha_threshold: u8,
+}
+
+impl Default for CanvasOptions {
+    fn default() -> Self {
+        Self {
+            width: 2000,
+            height: 2000,
+            fill_ratio: 0.85,
+            alignment: Alignment::Center,
+            background: None,
+            alpha_threshold: 10,
+        }
+    }
+}
+
+impl CanvasOptions {
+    pub fn validate(&self) -> Result<()> {
+        if self.width == 0 || self.height == 0 {
+            return Err(Error::InvalidOptions(
+                "Canvas width and height must be greater than zero".to_string(),
+            ));
+        }
+        if self.width > crop::MAX_OUTPUT_SIZE || self.height > crop::MAX_OUTPUT_SIZE {
+            return Err(Error::InvalidOptions(format!(
+                "Canvas width and height must be at most {} pixels",
+                crop::MAX_OUTPUT_SIZE
+            )));
+        }
+        if !(self.fill_ratio > 0.0 && self.fill_ratio <= 1.0) {
+            return Err(Error::InvalidOptions(
+                "Canvas fill ratio must be between 0 and 1".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+// Scales the subject of a cutout to fill the configured share of a fixed size
+// canvas and positions it there. Images without any visible subject end up as
+// an empty canvas.
+pub fn fit_to_canvas(image: &RgbaImage, options: &CanvasOptions) -> RgbaImage {
+    let background = Rgba(options.background.unwrap_or([0, 0, 0, 0]));
+    let mut canvas = ImageBuffer::from_pixel(options.width, options.height, background);
+
+    let Some(bounds) = crop::subject_bounds(image, options.alpha_threshold) else {
+        return canvas;
+    };
+
+    let max_width = options.width as f32 * options.fill_ratio;
+    let max_height = options.height as f32 * options.fill_ratio;
+    let scale = (max_width / bounds.width as f32).min(max_height / bounds.height as f32);
+
+    let subject_width = ((bounds.width as f32 * scale).round() as u32).clamp(1, options.width);
+    let subject_height = ((bounds.height as f32 * scale).round() as u32).clamp(1, options.height);
+
+    let subject = imageops::crop_imm(image, bounds.x, bounds.y, bounds.width, bounds.height);
+    let subject = imageops::resize(
+        &*subject,
+        subject_width,
+        subject_height,
+        imageops::FilterType::Lanczos3,
+    );
+
+    let x = (options.width - subject_width) / 2;
+    let y = match options.alignment {
+        Alignment::Center => (options.height - subject_height) / 2,
+        Alignment::Bottom => {
+            // Keep the same bottom margin a full height subject would have
+            let margin = ((options.height as f32 - max_height) / 2.0).round() as u32;
+            options.height - subject_height - margin.min(options.height - subject_height)
+        }
+    };
+
+    imageops::overlay(&mut canvas, &subject, x as i64, y as i64);
+
+    canvas
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CanvasPreset {
+    pub name: String,
+    pub options: CanvasOptions,
+    // Built in presets ship with the app and can't be deleted
+    #[serde(default)]
+    pub builtin: bool,
+}
+
+pub fn builtin_presets() -> Vec<CanvasPreset> {
+    vec![
+        CanvasPreset {
+            name: "Marketplace Square".to_string(),
+            options: CanvasOptions {
+                width: 2000,
+                height: 2000,
+                fill_ratio: 0.85,
+                alignment: Alignment::Center,
+                background: Some([255, 255, 255, 255]),
+                ..Default::default()
+            },
+            builtin: true,
+        },
+        CanvasPreset {
+            name: "Portrait 4:5".to_string(),
+            options: CanvasOptions {
+                width: 1600,
+                height: 2000,
+                fill_ratio: 0.9,
+                alignment: Alignment::Bottom,
+                background: None,
+                ..Default::default()
+            },
+            builtin: true,
+        },
+    ]
+}
+
+fn presets_path() -> Result<PathBuf> {
+    let base = directories::BaseDirs::new().ok_or(Error::HomeDirNotFound)?;
+    Ok(base.config_dir().join("rmbg").join("canvas_presets.json"))
+}
+
+fn load_user_presets() -> Result<Vec<CanvasPreset>> {
+    let path = presets_path()?;
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let contents = std::fs::read(&path).map_err(Error::file(&path))?;
+    serde_json::from_slice(&contents).map_err(|source| Error::InvalidPresetFile { path, source })
+}
+
+fn save_user_presets(presets: &[CanvasPreset]) -> Result<()> {
+    let path = presets_path()?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(Error::file(parent))?;
+    }
+
+    // Failing to serialize is a failure to write the file, it says nothing
+    // about the file being invalid
+    let contents = serde_json::to_vec_pretty(presets)
+        .map_err(std::io::Error::from)
+        .map_err(Error::file(&path))?;
+    std::fs::write(&path, contents).map_err(Error::file(&path))
+}
+
+// Built in presets followed by the user's, where a user preset replaces a
+// built in one with the same name
+pub fn get_presets() -> Result<Vec<CanvasPreset>> {
+    let user_presets = load_user_presets()?;
+
+    let mut presets: Vec<CanvasPreset> = builtin_presets()
+        .into_iter()
+        .filter(|builtin| !user_presets.iter().any(|p| p.name == builtin.name))
+        .collect();
+    presets.extend(user_presets);
+
+    Ok(presets)
+}
+
+pub fn get_preset(name: &str) -> Result<CanvasPreset> {
+    get_presets()?
+        .into_iter()
+        .find(|p| p.name == name)
+        .ok_or_else(|| Error::PresetNotFound(name.to_string()))
+}
+
+pub fn save_preset(name: &str, options: CanvasOptions) -> Result<CanvasPreset> {
+    options.validate()?;
+
+    let preset = CanvasPreset {
+        name: name.to_string(),
+        options,
+        builtin: false,
+    };
+
+    let mut presets = load_user_presets()?;
+    match presets.iter_mut().find(|p| p.name == name) {
+        Some(existing) => *existing = preset.clone(),
+        None => presets.push(preset.clone()),
+    }
+    save_user_presets(&presets)?;
+
+    Ok(preset)
+}
+
+pub fn delete_preset(name: &str) -> Result<()> {
+    let mut presets = load_user_presets()?;
+    let count = presets.len();
+    presets.retain(|p| p.name != name);
+
+    if presets.len() == count {
+        return Err(Error::PresetNotFound(name.to_string()));
+    }
+
+    save_user_presets(&presets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A transparent image with an opaque rectangle
+    fn cutout(width: u32, height: u32, subject: (u32, u32, u32, u32)) -> RgbaImage {
+        let (sx, sy, sw, sh) = subject;
+        RgbaImage::from_fn(width, height, |x, y| {
+            let inside = (sx..sx + sw).contains(&x) && (sy..sy + sh).contains(&y);
+            Rgba([0, 0, 255, if inside { 255 } else { 0 }])
+        })
+    }
+
+    fn canvas(width: u32, height: u32, alignment: Alignment) -> CanvasOptions {
+        CanvasOptions {
+            width,
+            height,
+            fill_ratio: 0.5,
+            alignment,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn scales_the_subject_to_the_fill_ratio() {
+        let image = cutout(300, 300, (50, 100, 200, 100));
+        let fitted = fit_to_canvas(&image, &canvas(400, 400, Alignment::Center));
+        assert_eq!(fitted.dimensions(), (400, 400));
+
+        let bounds = crop::subject_bounds(&fitted, 10).unwrap();
+        assert_eq!((bounds.width, bounds.height), (200, 100));
+        assert_eq!((bounds.x, bounds.y), (100, 150));
+    }
+
+    #[test]
+    fn bottom_alignment_keeps_the_bottom_margin() {
+        let image = cutout(300, 300, (50, 100, 200, 100));
+        let fitted = fit_to_canvas(&image, &canvas(400, 400, Alignment::Bottom));
+
+        let bounds = crop::subject_bounds(&fitted, 10).unwrap();
+        // A full height subject would leave 100 pixels below it
+        assert_eq!(bounds.y + bounds.height, 300);
+    }
+
+    #[test]
+    fn empty_cutout_gives_an_empty_canvas() {
+        let options = CanvasOptions {
+            background: Some([255, 255, 255, 255]),
+            ..canvas(10, 20, Alignment::Center)
+        };
+        let fitted = fit_to_canvas(&RgbaImage::new(30, 30), &options);
+        assert_eq!(fitted.dimensions(), (10, 20));
+        assert!(fitted.pixels().all(|pixel| pixel.0 == [255, 255, 255, 255]));
+    }
+
+    #[test]
+    fn rejects_invalid_sizes() {
+        assert!(canvas(0, 100, Alignment::Center).validate().is_err());
+        assert!(canvas(100_000, 100_000, Alignment::Center)
+            .validate()
+            .is_err());
+        let options = CanvasOptions {
+            fill_ratio: f32::NAN,
+            ..Default::default()
+        };
+        assert!(options.validate().is_err());
+        assert!(builtin_presets()
+            .iter()
+            .all(|preset| preset.options.validate().is_ok()));
+    }
+}
@@ -0,0 +1,281 @@
+use image::{imageops, ImageBuffer, Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, Result};
+
+// Limits that keep the padded canvas within a sane size
+const MAX_PADDING_PIXELS: u32 = 10_000;
+const MAX_PADDING_PERCENT: f32 = 1000.0;
+// Wider or taller than this is a strip rather than an image
+const MAX_ASPECT_RATIO: f32 = 100.0;
+// Longest side of an output image
+pub(crate) const MAX_OUTPUT_SIZE: u32 = 32_768;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Padding {
+    Pixels(u32),
+    // Percentage of the subject's longer side
+    Percent(f32),
+}
+
+impl Default for Padding {
+    fn default() -> Self {
+        Self::Pixels(0)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CropOptions {
+    // Pixels with alpha above this count as part of the subject
+    pub alpha_threshold: u8,
+    pub padding: Padding,
+    // Width divided by height. When set, the cropped subject is centered on a
+    // transparent canvas of this aspect ratio.
+    pub aspect_ratio: Option<f32>,
+}
+
+impl Default for CropOptions {
+    fn default() -> Self {
+        Self {
+            alpha_threshold: 10,
+            padding: Padding::default(),
+            aspect_ratio: None,
+        }
+    }
+}
+
+impl CropOptions {
+    pub fn validate(&self) -> Result<()> {
+        match self.padding {
+            Padding::Pixels(pixels) if pixels > MAX_PADDING_PIXELS => {
+                return Err(Error::InvalidOptions(format!(
+                    "Crop padding must be at most {} pixels",
+                    MAX_PADDING_PIXELS
+                )));
+            }
+            Padding::Percent(percent) if !(0.0..=MAX_PADDING_PERCENT).contains(&percent) => {
+                return Err(Error::InvalidOptions(format!(
+                    "Crop padding must be between 0% and {}%",
+                    MAX_PADDING_PERCENT
+                )));
+            }
+            _ => {}
+        }
+        if let Some(aspect_ratio) = self.aspect_ratio {
+            if !(1.0 / MAX_ASPECT_RATIO..=MAX_ASPECT_RATIO).contains(&aspect_ratio) {
+                return Err(Error::InvalidOptions(format!(
+                    "Crop aspect ratio must be between 1:{0} and {0}:1",
+                    MAX_ASPECT_RATIO
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BoundingBox {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+// Finds the smallest rectangle containing every pixel with alpha above the
+// threshold, or `None` if there are no such pixels
+pub fn subject_bounds(image: &RgbaImage, alpha_threshold: u8) -> Option<BoundingBox> {
+    let width = image.width();
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (u32::MAX, u32::MAX, 0, 0);
+
+    for (i, pixel) in image.as_raw().chunks_exact(4).enumerate() {
+        if pixel[3] > alpha_threshold {
+            let x = i as u32 % width;
+            let y = i as u32 / width;
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+        }
+    }
+
+    (min_x <= max_x).then(|| BoundingBox {
+        x: min_x,
+        y: min_y,
+        width: max_x - min_x + 1,
+        height: max_y - min_y + 1,
+    })
+}
+
+// Crops a cutout to its subject. Images without any visible subject are
+// returned unchanged.
+pub fn crop_to_subject(image: &RgbaImage, options: &CropOptions) -> Result<RgbaImage> {
+    let Some(bounds) = subject_bounds(image, options.alpha_threshold) else {
+        return Ok(image.clone());
+    };
+    let (canvas_width, canvas_height) = crop_size(bounds, options).ok_or_else(|| {
+        Error::InvalidOptions(format!(
+            "Cropped image would be larger than {0}x{0} pixels",
+            MAX_OUTPUT_SIZE
+        ))
+    })?;
+
+    let subject =
+        imageops::crop_imm(image, bounds.x, bounds.y, bounds.width, bounds.height).to_image();
+
+    // Padding may reach past the original image, so the subject is placed on
+    // a fresh transparent canvas rather than cropping with a larger rect
+    let mut canvas = ImageBuffer::from_pixel(canvas_width, canvas_height, Rgba([0, 0, 0, 0]));
+    imageops::replace(
+        &mut canvas,
+        &subject,
+        ((canvas_width - bounds.width) / 2) as i64,
+        ((canvas_height - bounds.height) / 2) as i64,
+    );
+
+    Ok(canvas)
+}
+
+// Size of the padded canvas around the subject's bounds, `None` past the
+// output size limit
+fn crop_size(bounds: BoundingBox, options: &CropOptions) -> Option<(u32, u32)> {
+    let padding = match options.padding {
+        Padding::Pixels(pixels) => pixels,
+        Padding::Percent(percent) => {
+            output_side(bounds.width.max(bounds.height) as f64 * percent.max(0.0) as f64 / 100.0)?
+        }
+    };
+
+    let mut canvas_width = output_side(bounds.width as f64 + padding as f64 * 2.0)?;
+    let mut canvas_height = output_side(bounds.height as f64 + padding as f64 * 2.0)?;
+
+    // Grow the shorter side to reach the requested aspect ratio
+    if let Some(aspect_ratio) = options.aspect_ratio.filter(|r| *r > 0.0) {
+        let aspect_ratio = aspect_ratio as f64;
+        if (canvas_width as f64 / canvas_height as f64) < aspect_ratio {
+            canvas_width = canvas_width.max(output_side(canvas_height as f64 * aspect_ratio)?);
+        } else {
+            canvas_height = canvas_height.max(output_side(canvas_width as f64 / aspect_ratio)?);
+        }
+    }
+
+    Some((canvas_width, canvas_height))
+}
+
+fn output_side(size: f64) -> Option<u32> {
+    let size = size.round();
+    (size <= MAX_OUTPUT_SIZE as f64).then_some(size as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A transparent image with an opaque rectangle
+    fn cutout(width: u32, height: u32, subject: BoundingBox) -> RgbaImage {
+        RgbaImage::from_fn(width, height, |x, y| {
+            let inside = (subject.x..subject.x + subject.width).contains(&x)
+                && (subject.y..subject.y + subject.height).contains(&y);
+            Rgba([255, 0, 0, if inside { 255 } else { 0 }])
+        })
+    }
+
+    const SUBJECT: BoundingBox = BoundingBox {
+        x: 10,
+        y: 20,
+        width: 30,
+        height: 10,
+    };
+
+    #[test]
+    fn finds_the_subject_bounds() {
+        let image = cutout(100, 50, SUBJECT);
+        assert_eq!(subject_bounds(&image, 10), Some(SUBJECT));
+        assert_eq!(subject_bounds(&RgbaImage::new(10, 10), 10), None);
+    }
+
+    #[test]
+    fn crops_with_padding() {
+        let image = cutout(100, 50, SUBJECT);
+        let options = CropOptions {
+            padding: Padding::Pixels(5),
+            ..Default::default()
+        };
+        let cropped = crop_to_subject(&image, &options).unwrap();
+        assert_eq!(cropped.dimensions(), (40, 20));
+        assert_eq!(cropped.get_pixel(4, 4)[3], 0);
+        assert_eq!(cropped.get_pixel(5, 5)[3], 255);
+
+        // A percentage of the longer side
+        let options = CropOptions {
+            padding: Padding::Percent(10.0),
+            ..Default::default()
+        };
+        let cropped = crop_to_subject(&image, &options).unwrap();
+        assert_eq!(cropped.dimensions(), (36, 16));
+    }
+
+    #[test]
+    fn grows_the_shorter_side_to_the_aspect_ratio() {
+        let image = cutout(100, 50, SUBJECT);
+        let options = CropOptions {
+            aspect_ratio: Some(1.0),
+            ..Default::default()
+        };
+        let cropped = crop_to_subject(&image, &options).unwrap();
+        assert_eq!(cropped.dimensions(), (30, 30));
+        // Centered vertically
+        assert_eq!(cropped.get_pixel(0, 9)[3], 0);
+        assert_eq!(cropped.get_pixel(0, 10)[3], 255);
+    }
+
+    #[test]
+    fn leaves_images_without_a_subject_alone() {
+        let image = RgbaImage::new(20, 10);
+        let cropped = crop_to_subject(&image, &CropOptions::default()).unwrap();
+        assert_eq!(cropped.dimensions(), (20, 10));
+    }
+
+    #[test]
+    fn rejects_oversized_crops() {
+        let image = cutout(100, 50, SUBJECT);
+        for options in [
+            CropOptions {
+                padding: Padding::Pixels(u32::MAX),
+                ..Default::default()
+            },
+            CropOptions {
+                padding: Padding::Percent(f32::MAX),
+                ..Default::default()
+            },
+            CropOptions {
+                aspect_ratio: Some(1e-30),
+                ..Default::default()
+            },
+        ] {
+            assert!(options.validate().is_err());
+            // Unvalidated options fail instead of overflowing
+            assert!(crop_to_subject(&image, &options).is_err());
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_options() {
+        for padding in [f32::NAN, f32::INFINITY, -1.0] {
+            let options = CropOptions {
+                padding: Padding::Percent(padding),
+                ..Default::default()
+            };
+            assert!(options.validate().is_err());
+        }
+        for aspect_ratio in [f32::NAN, 0.0, -1.0, 1000.0] {
+            let options = CropOptions {
+                aspect_ratio: Some(aspect_ratio),
+                ..Default::default()
+            };
+            assert!(options.validate().is_err());
+        }
+        assert!(CropOptions::default().validate().is_ok());
+    }
+}
@@ -0,0 +1,83 @@
+use image::GrayImage;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::processor;
+use crate::{Error, Result};
+
+// Extensions of the image files picked up from folders
+const IMAGE_EXTENSIONS: [&str; 7] = ["png", "jpg", "jpeg", "webp", "bmp", "tif", "tiff"];
+
+// Image files among the given paths, with folders expanded to the images
+// directly inside them, sorted by name
+pub fn collect_images(paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut images = Vec::new();
+    for path in paths {
+        if !path.is_dir() {
+            images.push(path.clone());
+            continue;
+        }
+
+        let mut entries = Vec::new();
+        for entry in std::fs::read_dir(path).map_err(Error::file(path))? {
+            let entry_path = entry.map_err(Error::file(path))?.path();
+            if entry_path.is_file() && is_image(&entry_path) {
+                entries.push(entry_path);
+            }
+        }
+        entries.sort();
+        images.extend(entries);
+    }
+
+    Ok(images)
+}
+
+fn is_image(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| IMAGE_EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+}
+
+// Ground truth masks in a folder, matched to images by file stem, so
+// `shoe.jpg` is compared against `shoe.png`
+pub struct GroundTruth {
+    masks: HashMap<String, PathBuf>,
+}
+
+impl GroundTruth {
+    pub fn open(dir: &Path) -> Result<Self> {
+        let masks = collect_images(&[dir.to_path_buf()])?
+            .into_iter()
+            .filter_map(|path| Some((path.file_stem()?.to_string_lossy().to_string(), path)))
+            .collect();
+        Ok(Self { masks })
+    }
+
+    // The mask for an image, `None` if there's none. Masks must have the
+    // image's dimensions, color masks are converted to grayscale.
+    pub fn mask_for(
+        &self,
+        image_path: &Path,
+        width: u32,
+        height: u32,
+    ) -> Result<Option<GrayImage>> {
+        let Some(stem) = image_path.file_stem() else {
+            return Ok(None);
+        };
+        let Some(path) = self.masks.get(stem.to_string_lossy().as_ref()) else {
+            return Ok(None);
+        };
+
+        let bytes = std::fs::read(path).map_err(Error::file(path))?;
+        let mask = processor::decode_image(&bytes)?.to_luma8();
+        if mask.dimensions() != (width, height) {
+            return Err(Error::GroundTruthSizeMismatch {
+                path: path.clone(),
+                mask_size: mask.dimensions(),
+                image_size: (width, height),
+            });
+        }
+
+        Ok(Some(mask))
+    }
+}
@@ -0,0 +1,92 @@
+use futures_util::StreamExt;
+use std::path::Path;
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+
+use crate::models::{self, ModelFile, Precision};
+use crate::{Error, Result};
+
+pub struct DownloadProgress {
+    pub downloaded: u64,
+    pub total: u64,
+}
+
+pub async fn download_file(
+    url: &str,
+    dest_path: &Path,
+    progress_callback: impl Fn(DownloadProgress),
+) -> Result<()> {
+    let client = reqwest::Client::new();
+    let response = client.get(url).send().await?;
+
+    if !response.status().is_success() {
+        return Err(Error::DownloadFailed(response.status()));
+    }
+
+    let total_size = response.content_length().unwrap_or(0);
+
+    // Create parent directory if it doesn't exist
+    if let Some(parent) = dest_path.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .map_err(Error::file(parent))?;
+    }
+
+    let mut file = File::create(dest_path)
+        .await
+        .map_err(Error::file(dest_path))?;
+    let mut downloaded: u64 = 0;
+    let mut stream = response.bytes_stream();
+
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        file.write_all(&chunk)
+            .await
+            .map_err(Error::file(dest_path))?;
+        downloaded += chunk.len() as u64;
+
+        progress_callback(DownloadProgress {
+            downloaded,
+            total: total_size,
+        });
+    }
+
+    file.flush().await?;
+    Ok(())
+}
+
+// Downloads every file of a model into the models directory, skipping files
+// that are already there
+pub async fn download_model(
+    model_id: &str,
+    progress_callback: impl Fn(&ModelFile, DownloadProgress),
+) -> Result<()> {
+    download_variant(model_id, Precision::Fp32, progress_callback).await
+}
+
+pub async fn download_variant(
+    model_id: &str,
+    precision: Precision,
+    progress_callback: impl Fn(&ModelFile, DownloadProgress),
+) -> Result<()> {
+    let model = models::get_model_by_id(model_id)
+        .ok_or_else(|| Error::ModelNotFound(model_id.to_string()))?;
+
+    let models_dir = models::get_models_dir()?;
+
+    for file in model.variant_files(precision)? {
+        let dest_path = models_dir.join(&file.name);
+
+        // Skip if file already exists
+        if dest_path.exists() {
+            continue;
+        }
+
+        download_file(&file.url, &dest_path, |progress| {
+            progress_callback(file, progress)
+        })
+        .await?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,214 @@
+use image::{imageops, GrayImage, Luma, Pixel, Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+use crate::{crop, mask};
+use crate::{Error, Result};
+
+// Pixels with alpha above this count as part of the subject when finding
+// where it stands
+const ALPHA_THRESHOLD: u8 = 10;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DropShadow {
+    // Shadow offset in pixels, positive values move it right and down
+    pub offset_x: i32,
+    pub offset_y: i32,
+    // Gaussian blur sigma of the shadow's edge
+    pub blur: f32,
+    pub opacity: f32,
+    pub color: [u8; 3],
+}
+
+impl Default for DropShadow {
+    fn default() -> Self {
+        Self {
+            offset_x: 12,
+            offset_y: 12,
+            blur: 10.0,
+            opacity: 0.5,
+            color: [0, 0, 0],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ContactShadow {
+    // Height of the shadow as a fraction of the subject's height, the
+    // subject's silhouette is squashed to this height at its base
+    pub height: f32,
+    pub blur: f32,
+    pub opacity: f32,
+    pub color: [u8; 3],
+}
+
+impl Default for ContactShadow {
+    fn default() -> Self {
+        Self {
+            height: 0.08,
+            blur: 6.0,
+            opacity: 0.6,
+            color: [0, 0, 0],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Reflection {
+    // Opacity right below the subject, fading out to nothing
+    pub opacity: f32,
+    // Length of the reflection as a fraction of the subject's height
+    pub length: f32,
+    // Pixels between the subject's base and the reflection
+    pub gap: u32,
+}
+
+impl Default for Reflection {
+    fn default() -> Self {
+        Self {
+            opacity: 0.3,
+            length: 0.4,
+            gap: 0,
+        }
+    }
+}
+
+// Effects drawn beneath the subject when it's placed on a background,
+// in the order they're given
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Effect {
+    DropShadow(DropShadow),
+    ContactShadow(ContactShadow),
+    Reflection(Reflection),
+}
+
+impl Effect {
+    pub fn validate(&self) -> Result<()> {
+        let (opacity, blur) = match self {
+            Effect::DropShadow(shadow) => (shadow.opacity, shadow.blur),
+            Effect::ContactShadow(shadow) => {
+                if !(shadow.height.is_finite() && shadow.height > 0.0) {
+                    return Err(Error::InvalidOptions(
+                        "Contact shadow height must be greater than zero".to_string(),
+                    ));
+                }
+                (shadow.opacity, shadow.blur)
+            }
+            Effect::Reflection(reflection) => {
+                if !(reflection.length.is_finite() && reflection.length > 0.0) {
+                    return Err(Error::InvalidOptions(
+                        "Reflection length must be greater than zero".to_string(),
+                    ));
+                }
+                (reflection.opacity, 0.0)
+            }
+        };
+
+        if !(0.0..=1.0).contains(&opacity) {
+            return Err(Error::InvalidOptions(
+                "Effect opacity must be between 0 and 1".to_string(),
+            ));
+        }
+        if !(blur.is_finite() && blur >= 0.0) {
+            return Err(Error::InvalidOptions(
+                "Effect blur must be a non-negative number".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+// Draws the effects for a cutout onto a background of the same size. The
+// subject itself isn't drawn.
+pub fn render(canvas: &mut RgbaImage, subject: &RgbaImage, effects: &[Effect]) {
+    for effect in effects {
+        match effect {
+            Effect::DropShadow(shadow) => drop_shadow(canvas, subject, shadow),
+            Effect::ContactShadow(shadow) => contact_shadow(canvas, subject, shadow),
+            Effect::Reflection(reflection) => reflect(canvas, subject, reflection),
+        }
+    }
+}
+
+fn drop_shadow(canvas: &mut RgbaImage, subject: &RgbaImage, shadow: &DropShadow) {
+    let (width, height) = subject.dimensions();
+    let mut alpha = GrayImage::new(width, height);
+
+    for (x, y, pixel) in subject.enumerate_pixels() {
+        let shadow_x = x as i64 + shadow.offset_x as i64;
+        let shadow_y = y as i64 + shadow.offset_y as i64;
+        if (0..width as i64).contains(&shadow_x) && (0..height as i64).contains(&shadow_y) {
+            alpha.put_pixel(shadow_x as u32, shadow_y as u32, Luma([pixel[3]]));
+        }
+    }
+
+    mask::feather(&mut alpha, shadow.blur);
+    paint(canvas, &alpha, shadow.color, shadow.opacity);
+}
+
+fn contact_shadow(canvas: &mut RgbaImage, subject: &RgbaImage, shadow: &ContactShadow) {
+    let Some(bounds) = crop::subject_bounds(subject, ALPHA_THRESHOLD) else {
+        return;
+    };
+
+    // Squash the silhouette flat and center it on the subject's base, as if
+    // lit from straight above
+    let silhouette = GrayImage::from_fn(bounds.width, bounds.height, |x, y| {
+        Luma([subject.get_pixel(bounds.x + x, bounds.y + y)[3]])
+    });
+    let shadow_height = ((bounds.height as f32 * shadow.height).round() as u32).max(1);
+    let squashed = imageops::resize(
+        &silhouette,
+        bounds.width,
+        shadow_height,
+        imageops::FilterType::Triangle,
+    );
+
+    let mut alpha = GrayImage::new(subject.width(), subject.height());
+    let top = (bounds.y + bounds.height) as i64 - (shadow_height / 2) as i64;
+    imageops::replace(&mut alpha, &squashed, bounds.x as i64, top);
+
+    mask::feather(&mut alpha, shadow.blur);
+    paint(canvas, &alpha, shadow.color, shadow.opacity);
+}
+
+fn reflect(canvas: &mut RgbaImage, subject: &RgbaImage, reflection: &Reflection) {
+    let Some(bounds) = crop::subject_bounds(subject, ALPHA_THRESHOLD) else {
+        return;
+    };
+
+    let base = bounds.y + bounds.height + reflection.gap;
+    let length = ((bounds.height as f32 * reflection.length).round() as u32).min(bounds.height);
+
+    for row in 0..length {
+        let y = base + row;
+        if y >= canvas.height() {
+            break;
+        }
+
+        // Mirror the subject upwards from its base, fading out linearly
+        let source_y = bounds.y + bounds.height - 1 - row;
+        let fade = reflection.opacity * (1.0 - row as f32 / length as f32);
+
+        for x in bounds.x..bounds.x + bounds.width {
+            let mut pixel = *subject.get_pixel(x, source_y);
+            pixel[3] = (pixel[3] as f32 * fade).round() as u8;
+            if pixel[3] > 0 {
+                canvas.get_pixel_mut(x, y).blend(&pixel);
+            }
+        }
+    }
+}
+
+// Blends a solid color onto the canvas through an alpha mask
+fn paint(canvas: &mut RgbaImage, alpha: &GrayImage, [r, g, b]: [u8; 3], opacity: f32) {
+    for (pixel, value) in canvas.pixels_mut().zip(alpha.pixels()) {
+        let alpha = (value[0] as f32 * opacity).round() as u8;
+        if alpha > 0 {
+            pixel.blend(&Rgba([r, g, b, alpha]));
+        }
+    }
+}
@@ -0,0 +1,323 @@
+use image::{DynamicImage, GrayImage};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::models::Precision;
+use crate::processor::{self, InferenceOptions, ProcessOptions, RemoverCache};
+use crate::session::{Provider, SessionOptions};
+use crate::{Error, Result};
+
+// How the models' masks are combined into one
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Fusion {
+    // Weighted average
+    #[default]
+    Mean,
+    // Keeps whatever any model sees as subject
+    Max,
+    // Weighted median, outvotes a single model that's off
+    Median,
+    // Weighted average favoring, for each pixel, the models most sure of it
+    Confidence,
+}
+
+impl Fusion {
+    pub fn id(&self) -> &'static str {
+        match self {
+            Fusion::Mean => "mean",
+            Fusion::Max => "max",
+            Fusion::Median => "median",
+            Fusion::Confidence => "confidence",
+        }
+    }
+
+    pub fn from_id(id: &str) -> Option<Self> {
+        [
+            Fusion::Mean,
+            Fusion::Max,
+            Fusion::Median,
+            Fusion::Confidence,
+        ]
+        .into_iter()
+        .find(|fusion| fusion.id() == id)
+    }
+}
+
+impl std::fmt::Display for Fusion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(self.id())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EnsembleMember {
+    pub model_id: String,
+    #[serde(default = "default_weight")]
+    pub weight: f32,
+}
+
+fn default_weight() -> f32 {
+    1.0
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EnsembleOptions {
+    pub models: Vec<EnsembleMember>,
+    pub fusion: Fusion,
+}
+
+impl EnsembleOptions {
+    pub fn validate(&self) -> Result<()> {
+        if self.models.is_empty() {
+            return Err(Error::InvalidOptions(
+                "An ensemble needs at least one model".to_string(),
+            ));
+        }
+        if let Some(member) = self
+            .models
+            .iter()
+            .find(|member| !(member.weight.is_finite() && member.weight > 0.0))
+        {
+            return Err(Error::InvalidOptions(format!(
+                "Weight of {} must be a positive number",
+                member.model_id
+            )));
+        }
+        Ok(())
+    }
+}
+
+// Several models run over each image, with their masks fused into one before
+// the mask operations and output options. Each model is loaded once and
+// kept for the images after.
+pub struct Ensemble {
+    options: EnsembleOptions,
+    removers: RemoverCache,
+}
+
+impl Ensemble {
+    // Loads all the models up front, so a missing one fails before any image
+    // is processed
+    pub fn new(
+        options: EnsembleOptions,
+        precision: Precision,
+        session: &SessionOptions,
+    ) -> Result<Self> {
+        options.validate()?;
+        session.validate()?;
+
+        // Every model runs on every image, so all of them stay loaded
+        let mut model_ids: Vec<&str> = options
+            .models
+            .iter()
+            .map(|member| member.model_id.as_str())
+            .collect();
+        model_ids.sort_unstable();
+        model_ids.dedup();
+        let mut removers = RemoverCache::with_capacity(precision, session, model_ids.len());
+        for member in &options.models {
+            removers.get(&member.model_id)?;
+        }
+
+        Ok(Self { options, removers })
+    }
+
+    // Execution provider of the first model. The others run on the same one
+    // unless it failed to load them.
+    pub fn provider(&mut self) -> Result<Provider> {
+        let model_id = &self.options.models[0].model_id;
+        Ok(self.removers.get(model_id)?.provider())
+    }
+
+    pub fn predict_mask(
+        &mut self,
+        image: &DynamicImage,
+        options: &InferenceOptions,
+    ) -> Result<GrayImage> {
+        let mut masks = Vec::with_capacity(self.options.models.len());
+        let mut weights = Vec::with_capacity(self.options.models.len());
+        for member in &self.options.models {
+            let remover = self.removers.get(&member.model_id)?;
+            masks.push(remover.predict_mask_with_options(image, options)?);
+            weights.push(member.weight);
+        }
+
+        fuse_masks(&masks, &weights, self.options.fusion)
+    }
+}
+
+// Combines masks of the same size into one, each weighted as given. `Max`
+// ignores the weights.
+pub fn fuse_masks(masks: &[GrayImage], weights: &[f32], fusion: Fusion) -> Result<GrayImage> {
+    let Some(first) = masks.first() else {
+        return Err(Error::InvalidOptions("No masks to fuse".to_string()));
+    };
+    if weights.len() != masks.len() {
+        return Err(Error::InvalidOptions(
+            "Every mask needs a weight".to_string(),
+        ));
+    }
+    if masks
+        .iter()
+        .any(|mask| mask.dimensions() != first.dimensions())
+    {
+        return Err(Error::InvalidOptions(
+            "Masks to fuse must have the same size".to_string(),
+        ));
+    }
+
+    let mut fused = GrayImage::new(first.width(), first.height());
+    let mut values = Vec::with_capacity(masks.len());
+    for (index, pixel) in fused.iter_mut().enumerate() {
+        values.clear();
+        values.extend(
+            masks
+                .iter()
+                .zip(weights)
+                .map(|(mask, &weight)| (mask.as_raw()[index] as f32 / 255.0, weight)),
+        );
+
+        let value = match fusion {
+            Fusion::Mean => weighted_mean(values.iter().copied()),
+            Fusion::Max => values.iter().fold(0.0, |max, &(value, _)| value.max(max)),
+            Fusion::Median => weighted_median(&mut values),
+            // Confidence is how far a model is from undecided. The small
+            // floor keeps pixels where every model is unsure at the mean.
+            Fusion::Confidence => weighted_mean(values.iter().map(|&(value, weight)| {
+                let confidence = (value - 0.5).abs() * 2.0;
+                (value, weight * (confidence + 1e-3))
+            })),
+        };
+        *pixel = (value * 255.0).round().clamp(0.0, 255.0) as u8;
+    }
+
+    Ok(fused)
+}
+
+fn weighted_mean(values: impl Iterator<Item = (f32, f32)>) -> f32 {
+    let (sum, total) = values.fold((0.0, 0.0), |(sum, total), (value, weight)| {
+        (sum + value * weight, total + weight)
+    });
+    if total > 0.0 {
+        sum / total
+    } else {
+        0.0
+    }
+}
+
+// The value at which half the total weight lies on either side
+fn weighted_median(values: &mut [(f32, f32)]) -> f32 {
+    values.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let half = values.iter().map(|&(_, weight)| weight).sum::<f32>() / 2.0;
+
+    let mut covered = 0.0;
+    for (index, &(value, weight)) in values.iter().enumerate() {
+        covered += weight;
+        if covered > half {
+            return value;
+        }
+        // Exactly half: between this value and the next, like the median
+        // of an even count
+        if covered == half {
+            let next = values.get(index + 1).map_or(value, |&(next, _)| next);
+            return (value + next) / 2.0;
+        }
+    }
+    values.last().map_or(0.0, |&(value, _)| value)
+}
+
+// `processor::process_image_bytes_with` with the ensemble's fused mask
+pub fn process_image_bytes(
+    ensemble: &mut Ensemble,
+    input: &[u8],
+    options: &ProcessOptions,
+) -> Result<Vec<u8>> {
+    options.validate()?;
+
+    let (image, metadata) = processor::decode_image_with_metadata(input)?;
+    let mask = ensemble.predict_mask(&image, &options.inference)?;
+    processor::finish_image(&image, &metadata, mask, options)
+}
+
+pub fn process_image(
+    ensemble: &mut Ensemble,
+    input_path: &Path,
+    output_path: &Path,
+    options: &ProcessOptions,
+) -> Result<()> {
+    let input = std::fs::read(input_path).map_err(Error::file(input_path))?;
+    let output = process_image_bytes(ensemble, &input, options)?;
+    std::fs::write(output_path, output).map_err(Error::file(output_path))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pixel(value: u8) -> GrayImage {
+        GrayImage::from_raw(1, 1, vec![value]).unwrap()
+    }
+
+    fn fuse(values: &[u8], weights: &[f32], fusion: Fusion) -> u8 {
+        let masks: Vec<_> = values.iter().map(|&value| pixel(value)).collect();
+        fuse_masks(&masks, weights, fusion).unwrap().as_raw()[0]
+    }
+
+    #[test]
+    fn fuses_with_equal_weights() {
+        let values = [0, 128, 255];
+        let weights = [1.0; 3];
+        assert_eq!(fuse(&values, &weights, Fusion::Mean), 128);
+        assert_eq!(fuse(&values, &weights, Fusion::Max), 255);
+        assert_eq!(fuse(&values, &weights, Fusion::Median), 128);
+        assert_eq!(fuse(&values, &weights, Fusion::Confidence), 128);
+    }
+
+    #[test]
+    fn weights_pull_toward_a_model() {
+        let values = [0, 128, 255];
+        let weights = [1.0, 1.0, 3.0];
+        assert_eq!(fuse(&values, &weights, Fusion::Mean), 179);
+        assert_eq!(fuse(&values, &weights, Fusion::Max), 255);
+        assert_eq!(fuse(&values, &weights, Fusion::Median), 255);
+        assert_eq!(fuse(&values, &weights, Fusion::Confidence), 191);
+    }
+
+    #[test]
+    fn weighted_median_splits_even_halves() {
+        assert_eq!(weighted_median(&mut [(0.8, 1.0), (0.2, 1.0)]), 0.5);
+        assert_eq!(
+            weighted_median(&mut [(0.8, 1.0), (0.2, 1.0), (0.4, 2.0)]),
+            0.4
+        );
+        assert_eq!(weighted_median(&mut [(0.3, 1.0)]), 0.3);
+        assert_eq!(weighted_median(&mut []), 0.0);
+    }
+
+    #[test]
+    fn rejects_mismatched_masks() {
+        assert!(fuse_masks(&[], &[], Fusion::Mean).is_err());
+        assert!(fuse_masks(&[pixel(0)], &[1.0, 1.0], Fusion::Mean).is_err());
+        let masks = [pixel(0), GrayImage::new(2, 1)];
+        assert!(fuse_masks(&masks, &[1.0, 1.0], Fusion::Mean).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_options() {
+        assert!(EnsembleOptions::default().validate().is_err());
+        for weight in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+            let options = EnsembleOptions {
+                models: vec![EnsembleMember {
+                    model_id: "u2net".to_string(),
+                    weight,
+                }],
+                fusion: Fusion::Mean,
+            };
+            assert!(options.validate().is_err());
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use std::path::PathBuf;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Model not found: {0}")]
+    ModelNotFound(String),
+
+    #[error("Model {model_id} has no {precision} variant")]
+    VariantNotFound {
+        model_id: String,
+        precision: crate::models::Precision,
+    },
+
+    #[error("Model has no files: {0}")]
+    ModelHasNoFiles(String),
+
+    #[error("Model file not found. Please download the model first.")]
+    ModelNotDownloaded { model_id: String, path: PathBuf },
+
+    #[error("Could not determine home directory")]
+    HomeDirNotFound,
+
+    #[error("Failed to download file: HTTP {0}")]
+    DownloadFailed(reqwest::StatusCode),
+
+    #[error("Could not build the model input: {0}")]
+    InvalidModelInput(String),
+
+    #[error("Invalid model output: {0}")]
+    InvalidModelOutput(String),
+
+    #[error("Invalid options: {0}")]
+    InvalidOptions(String),
+
+    #[error(
+        "Ground truth mask {} is {}x{}, its image is {}x{}",
+        path.display(), mask_size.0, mask_size.1, image_size.0, image_size.1
+    )]
+    GroundTruthSizeMismatch {
+        path: PathBuf,
+        mask_size: (u32, u32),
+        image_size: (u32, u32),
+    },
+
+    #[error("Preset not found: {0}")]
+    PresetNotFound(String),
+
+    #[error("Invalid presets file {}: {source}", path.display())]
+    InvalidPresetFile {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+
+    #[error("{}: {source}", path.display())]
+    File {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+
+    #[error(transparent)]
+    Image(#[from] image::ImageError),
+
+    #[error(transparent)]
+    Ort(#[from] ort::Error),
+}
+
+impl Error {
+    // Attaches the path an I/O error happened on, so callers can tell which
+    // file couldn't be read or written
+    pub(crate) fn file(path: impl Into<PathBuf>) -> impl FnOnce(std::io::Error) -> Self {
+        let path = path.into();
+        move |source| Self::File { path, source }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,135 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use crate::dataset::GroundTruth;
+use crate::metrics::MaskScores;
+use crate::models::{self, Precision};
+use crate::processor::{self, BackgroundRemover, InferenceOptions};
+use crate::session::SessionOptions;
+use crate::{Error, Result};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EvaluationOptions {
+    pub precision: Precision,
+    pub session: SessionOptions,
+    pub inference: InferenceOptions,
+}
+
+impl EvaluationOptions {
+    pub fn validate(&self) -> Result<()> {
+        self.session.validate()?;
+        self.inference.validate()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Evaluation {
+    pub model_id: String,
+    pub precision: Precision,
+    pub images: Vec<ImageEvaluation>,
+    // Mean over all evaluated images, `None` if none had a ground truth mask
+    pub mean: Option<MaskScores>,
+    // Images without a ground truth mask
+    pub skipped: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageEvaluation {
+    pub path: PathBuf,
+    #[serde(flatten)]
+    pub scores: MaskScores,
+}
+
+// Runs a model over the images and scores its masks against the ground truth
+// masks named like them. The model's own mask is scored, before any of the
+// mask operations or output options.
+pub fn evaluate_model(
+    model_id: &str,
+    images: &[PathBuf],
+    ground_truth_dir: &Path,
+    options: &EvaluationOptions,
+) -> Result<Evaluation> {
+    options.validate()?;
+
+    let ground_truth = GroundTruth::open(ground_truth_dir)?;
+    let model_path = models::get_variant_path(model_id, options.precision)?;
+    let mut remover = BackgroundRemover::with_options(&model_path, &options.session)?;
+
+    let mut evaluated = Vec::new();
+    let mut skipped = Vec::new();
+    for path in images {
+        let bytes = std::fs::read(path).map_err(Error::file(path))?;
+        let image = processor::decode_image(&bytes)?;
+        let Some(truth) = ground_truth.mask_for(path, image.width(), image.height())? else {
+            skipped.push(path.clone());
+            continue;
+        };
+
+        let mask = remover.predict_mask_with_options(&image, &options.inference)?;
+        evaluated.push(ImageEvaluation {
+            path: path.clone(),
+            scores: MaskScores::compare(&mask, &truth),
+        });
+    }
+
+    let scores: Vec<MaskScores> = evaluated.iter().map(|image| image.scores).collect();
+    Ok(Evaluation {
+        model_id: model_id.to_string(),
+        precision: options.precision,
+        mean: MaskScores::mean(&scores),
+        images: evaluated,
+        skipped,
+    })
+}
+
+impl Evaluation {
+    // One row per image and a final `mean` row
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("image,iou,f_measure,mae,boundary_f\n");
+        let mut row = |name: &str, scores: &MaskScores| {
+            let _ = writeln!(
+                csv,
+                "{},{:.4},{:.4},{:.4},{:.4}",
+                csv_field(name),
+                scores.iou,
+                scores.f_measure,
+                scores.mae,
+                scores.boundary_f
+            );
+        };
+
+        for image in &self.images {
+            row(&image.path.to_string_lossy(), &image.scores);
+        }
+        if let Some(mean) = &self.mean {
+            row("mean", mean);
+        }
+
+        csv
+    }
+
+    // Saves the results as `<model>-evaluation.json` and `.csv` in a folder
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        let json_path = dir.join(format!("{}-evaluation.json", self.model_id));
+        let json = serde_json::to_vec_pretty(self)
+            .map_err(std::io::Error::from)
+            .map_err(Error::file(&json_path))?;
+        std::fs::write(&json_path, json).map_err(Error::file(&json_path))?;
+
+        let csv_path = dir.join(format!("{}-evaluation.csv", self.model_id));
+        std::fs::write(&csv_path, self.to_csv()).map_err(Error::file(&csv_path))?;
+
+        Ok(())
+    }
+}
+
+// Quotes a field if it contains anything CSV gives a meaning to
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
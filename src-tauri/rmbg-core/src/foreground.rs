@@ -0,0 +1,120 @@
+use image::RgbaImage;
+
+use crate::blur;
+
+// Window radii of the coarse and the refining pass
+const COARSE_RADIUS: usize = 45;
+const FINE_RADIUS: usize = 3;
+
+// Avoids dividing by zero where a window holds no foreground or background
+const EPSILON: f32 = 1e-5;
+
+// Re-estimates the color of partially transparent pixels so they no longer
+// carry the old background's color, like green fringes from a green screen.
+// Uses blur fusion (Forte & Pitié, "Approximate Fast Foreground Colour
+// Estimation"): the local foreground and background colors are averaged
+// around each pixel and the pixel's color is solved for the foreground part.
+pub fn decontaminate_colors(image: &mut RgbaImage) {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    if width == 0 || height == 0 {
+        return;
+    }
+
+    let pixels = image.as_raw();
+    let alpha: Vec<f32> = pixels
+        .chunks_exact(4)
+        .map(|p| p[3] as f32 / 255.0)
+        .collect();
+    let colors: Vec<[f32; 3]> = pixels
+        .chunks_exact(4)
+        .map(|p| {
+            [
+                p[0] as f32 / 255.0,
+                p[1] as f32 / 255.0,
+                p[2] as f32 / 255.0,
+            ]
+        })
+        .collect();
+
+    // The coarse pass starts from the image itself for both layers, the fine
+    // pass refines its foreground against its blurred background
+    let (foreground, background) = estimate(
+        &colors,
+        &colors,
+        &colors,
+        &alpha,
+        width,
+        height,
+        COARSE_RADIUS,
+    );
+    let (foreground, _) = estimate(
+        &colors,
+        &foreground,
+        &background,
+        &alpha,
+        width,
+        height,
+        FINE_RADIUS,
+    );
+
+    for ((pixel, &a), color) in image.chunks_exact_mut(4).zip(&alpha).zip(&foreground) {
+        // Opaque pixels are pure foreground already and transparent ones
+        // aren't visible, so only the edge in between is rewritten
+        if a > 0.0 && a < 1.0 {
+            for channel in 0..3 {
+                pixel[channel] = (color[channel].clamp(0.0, 1.0) * 255.0).round() as u8;
+            }
+        }
+    }
+}
+
+// One blur fusion pass, returning the foreground estimate and the blurred
+// background it was solved against
+fn estimate(
+    colors: &[[f32; 3]],
+    foreground: &[[f32; 3]],
+    background: &[[f32; 3]],
+    alpha: &[f32],
+    width: usize,
+    height: usize,
+    radius: usize,
+) -> (Vec<[f32; 3]>, Vec<[f32; 3]>) {
+    // Alpha weighted foreground and inverse alpha weighted background
+    // colors, plus the alpha itself, blurred together
+    let weighted: Vec<[f32; 7]> = (0..colors.len())
+        .map(|i| {
+            let (a, f, b) = (alpha[i], foreground[i], background[i]);
+            [
+                a,
+                f[0] * a,
+                f[1] * a,
+                f[2] * a,
+                b[0] * (1.0 - a),
+                b[1] * (1.0 - a),
+                b[2] * (1.0 - a),
+            ]
+        })
+        .collect();
+    let blurred = blur::box_blur(&weighted, width, height, radius);
+
+    let mut new_foreground = Vec::with_capacity(colors.len());
+    let mut new_background = Vec::with_capacity(colors.len());
+    for i in 0..colors.len() {
+        let [blurred_alpha, fr, fg, fb, br, bg, bb] = blurred[i];
+        let blurred_fg = [fr, fg, fb].map(|c| c / (blurred_alpha + EPSILON));
+        let blurred_bg = [br, bg, bb].map(|c| c / (1.0 - blurred_alpha + EPSILON));
+
+        let a = alpha[i];
+        let mut color = [0.0; 3];
+        for channel in 0..3 {
+            let residual =
+                colors[i][channel] - a * blurred_fg[channel] - (1.0 - a) * blurred_bg[channel];
+            color[channel] = (blurred_fg[channel] + a * residual).clamp(0.0, 1.0);
+        }
+
+        new_foreground.push(color);
+        new_background.push(blurred_bg);
+    }
+
+    (new_foreground, new_background)
+}
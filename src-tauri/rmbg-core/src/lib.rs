@@ -0,0 +1,45 @@
+//! Background removal using the rembg family of ONNX models.
+//!
+//! This crate holds everything the desktop app uses that doesn't depend on
+//! Tauri: the model catalog, model downloads and the inference pipeline.
+//!
+//! ```no_run
+//! use rmbg_core::{models, processor, BackgroundRemover};
+//!
+//! # fn main() -> rmbg_core::Result<()> {
+//! let model_path = models::get_model_path("u2netp")?;
+//! let mut remover = BackgroundRemover::new(&model_path)?;
+//!
+//! let input = processor::open_image("photo.jpg".as_ref())?;
+//! let output = remover.remove_background(&input)?;
+//! output.save("photo_no_bg.png")?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod background;
+pub mod benchmark;
+mod blur;
+pub mod canvas;
+pub mod crop;
+pub mod dataset;
+pub mod downloader;
+pub mod effects;
+pub mod ensemble;
+mod error;
+pub mod evaluation;
+pub mod foreground;
+pub mod mask;
+pub mod metadata;
+pub mod metrics;
+pub mod models;
+pub mod portrait;
+pub mod processor;
+mod resample;
+pub mod routing;
+pub mod session;
+pub mod tiling;
+pub mod upsampling;
+
+pub use error::{Error, Result};
+pub use processor::BackgroundRemover;
@@ -0,0 +1,430 @@
+use image::{imageops, GrayImage};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+use crate::{Error, Result};
+
+// Mask values at or above this count as foreground for the operations that
+// need a hard decision, like hole filling and island removal
+const FOREGROUND: u8 = 128;
+
+// How far, in pixels, the soft edge of a kept region reaches past its
+// foreground when dropping other regions
+const EDGE_WIDTH: u32 = 32;
+
+// A step applied to the predicted mask before it becomes the alpha channel.
+// Steps run in the order they're given.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum MaskOperation {
+    // Binarizes the mask, values at or above the threshold become opaque
+    Threshold { value: u8 },
+    // Shrinks the foreground by this many pixels
+    Erode { radius: u32 },
+    // Grows the foreground by this many pixels
+    Dilate { radius: u32 },
+    // Makes background regions fully enclosed by the subject opaque
+    FillHoles,
+    // Drops foreground regions smaller than this many pixels
+    RemoveIslands { min_area: u32 },
+    // Keeps only the largest foreground regions by area
+    KeepLargest { count: u32 },
+    // Softens edges with a Gaussian blur
+    Feather { sigma: f32 },
+}
+
+pub fn validate_operations(operations: &[MaskOperation]) -> Result<()> {
+    for operation in operations {
+        match *operation {
+            MaskOperation::Feather { sigma } if !(sigma.is_finite() && sigma >= 0.0) => {
+                return Err(Error::InvalidOptions(
+                    "Feather sigma must be a non-negative number".to_string(),
+                ));
+            }
+            MaskOperation::KeepLargest { count: 0 } => {
+                return Err(Error::InvalidOptions(
+                    "Keep largest needs a count of at least 1".to_string(),
+                ));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+pub fn apply_operations(mask: &mut GrayImage, operations: &[MaskOperation]) {
+    for operation in operations {
+        match *operation {
+            MaskOperation::Threshold { value } => threshold(mask, value),
+            MaskOperation::Erode { radius } => erode(mask, radius),
+            MaskOperation::Dilate { radius } => dilate(mask, radius),
+            MaskOperation::FillHoles => fill_holes(mask),
+            MaskOperation::RemoveIslands { min_area } => remove_islands(mask, min_area),
+            MaskOperation::KeepLargest { count } => keep_largest(mask, count),
+            MaskOperation::Feather { sigma } => feather(mask, sigma),
+        }
+    }
+}
+
+pub fn threshold(mask: &mut GrayImage, value: u8) {
+    for pixel in mask.iter_mut() {
+        *pixel = if *pixel >= value { 255 } else { 0 };
+    }
+}
+
+// Swaps subject and background
+pub fn invert(mask: &mut GrayImage) {
+    for pixel in mask.iter_mut() {
+        *pixel = 255 - *pixel;
+    }
+}
+
+pub fn erode(mask: &mut GrayImage, radius: u32) {
+    morphology(mask, radius, u8::min);
+}
+
+pub fn dilate(mask: &mut GrayImage, radius: u32) {
+    morphology(mask, radius, u8::max);
+}
+
+// Square min/max filter, done as a horizontal then a vertical pass
+fn morphology(mask: &mut GrayImage, radius: u32, pick: fn(u8, u8) -> u8) {
+    if radius == 0 {
+        return;
+    }
+
+    let (width, height) = (mask.width() as usize, mask.height() as usize);
+    let radius = radius as usize;
+    let source = mask.as_raw().clone();
+    let mut horizontal = vec![0u8; source.len()];
+
+    for y in 0..height {
+        let row = &source[y * width..(y + 1) * width];
+        for x in 0..width {
+            let start = x.saturating_sub(radius);
+            let end = (x + radius + 1).min(width);
+            horizontal[y * width + x] = row[start..end].iter().copied().reduce(pick).unwrap_or(0);
+        }
+    }
+
+    let output: &mut [u8] = mask;
+    for x in 0..width {
+        for y in 0..height {
+            let start = y.saturating_sub(radius);
+            let end = (y + radius + 1).min(height);
+            output[y * width + x] = (start..end)
+                .map(|row| horizontal[row * width + x])
+                .reduce(pick)
+                .unwrap_or(0);
+        }
+    }
+}
+
+pub fn fill_holes(mask: &mut GrayImage) {
+    let (width, height) = (mask.width() as usize, mask.height() as usize);
+    if width == 0 || height == 0 {
+        return;
+    }
+    let pixels: &mut [u8] = mask;
+
+    // Flood the background from the image border, whatever background it
+    // can't reach is enclosed by the subject
+    let mut reachable = vec![false; pixels.len()];
+    let mut queue = VecDeque::new();
+    for x in 0..width {
+        queue.push_back(x);
+        queue.push_back((height - 1) * width + x);
+    }
+    for y in 0..height {
+        queue.push_back(y * width);
+        queue.push_back(y * width + width - 1);
+    }
+
+    while let Some(index) = queue.pop_front() {
+        if reachable[index] || pixels[index] >= FOREGROUND {
+            continue;
+        }
+        reachable[index] = true;
+
+        let (x, y) = (index % width, index / width);
+        if x > 0 {
+            queue.push_back(index - 1);
+        }
+        if x + 1 < width {
+            queue.push_back(index + 1);
+        }
+        if y > 0 {
+            queue.push_back(index - width);
+        }
+        if y + 1 < height {
+            queue.push_back(index + width);
+        }
+    }
+
+    for (pixel, reachable) in pixels.iter_mut().zip(reachable) {
+        if !reachable {
+            *pixel = 255;
+        }
+    }
+}
+
+pub fn remove_islands(mask: &mut GrayImage, min_area: u32) {
+    let components = Components::label(mask);
+    let keep: Vec<bool> = components
+        .areas
+        .iter()
+        .map(|&area| area >= min_area as usize)
+        .collect();
+    retain_components(mask, &components, &keep);
+}
+
+pub fn keep_largest(mask: &mut GrayImage, count: u32) {
+    let components = Components::label(mask);
+
+    // Label 0 is the background, so it's left out of the ranking
+    let mut ranked: Vec<usize> = (1..components.areas.len()).collect();
+    ranked.sort_by_key(|&label| std::cmp::Reverse(components.areas[label]));
+
+    let mut keep = vec![false; components.areas.len()];
+    for &label in ranked.iter().take(count as usize) {
+        keep[label] = true;
+    }
+    retain_components(mask, &components, &keep);
+}
+
+// Clears every component not marked in `keep`. The faint edge around a
+// component is below the foreground cutoff and so isn't labeled, it's kept
+// only where it falls off from a kept component, otherwise dropped blobs would
+// leave a halo behind.
+fn retain_components(mask: &mut GrayImage, components: &Components, keep: &[bool]) {
+    let (width, height) = (mask.width() as usize, mask.height() as usize);
+    let pixels: &mut [u8] = mask;
+
+    let mut kept = vec![false; pixels.len()];
+    let mut queue = VecDeque::new();
+    for (index, &label) in components.labels.iter().enumerate() {
+        if label != 0 && keep[label as usize] {
+            kept[index] = true;
+            queue.push_back((index, 0));
+        }
+    }
+
+    // Grow the kept components into the unlabeled edge pixels around them,
+    // breadth first so the distance is known. Growth only goes downhill and
+    // only so far, so it can't cross faint background and climb into the
+    // edge of a dropped blob.
+    while let Some((index, distance)) = queue.pop_front() {
+        if distance == EDGE_WIDTH {
+            continue;
+        }
+        let (x, y) = (index % width, index / width);
+        for ny in y.saturating_sub(1)..(y + 2).min(height) {
+            for nx in x.saturating_sub(1)..(x + 2).min(width) {
+                let neighbor = ny * width + nx;
+                if !kept[neighbor]
+                    && components.labels[neighbor] == 0
+                    && pixels[neighbor] > 0
+                    && pixels[neighbor] <= pixels[index]
+                {
+                    kept[neighbor] = true;
+                    queue.push_back((neighbor, distance + 1));
+                }
+            }
+        }
+    }
+
+    for (pixel, kept) in pixels.iter_mut().zip(kept) {
+        if !kept {
+            *pixel = 0;
+        }
+    }
+}
+
+pub fn feather(mask: &mut GrayImage, sigma: f32) {
+    // The blur panics on an empty image
+    if sigma > 0.0 && !mask.is_empty() {
+        *mask = imageops::blur(mask, sigma);
+    }
+}
+
+// 8-connected foreground regions of a mask. Label 0 is background, regions
+// are numbered from 1.
+pub(crate) struct Components {
+    pub(crate) labels: Vec<u32>,
+    // Pixel count per label, indexed by label
+    pub(crate) areas: Vec<usize>,
+}
+
+impl Components {
+    pub(crate) fn label(mask: &GrayImage) -> Self {
+        let (width, height) = (mask.width() as usize, mask.height() as usize);
+        let pixels = mask.as_raw();
+
+        let mut labels = vec![0u32; pixels.len()];
+        let mut areas = vec![0];
+        let mut stack = Vec::new();
+
+        for start in 0..pixels.len() {
+            if labels[start] != 0 || pixels[start] < FOREGROUND {
+                continue;
+            }
+
+            let label = areas.len() as u32;
+            let mut area = 0;
+            labels[start] = label;
+            stack.push(start);
+
+            while let Some(index) = stack.pop() {
+                area += 1;
+                let (x, y) = (index % width, index / width);
+
+                for ny in y.saturating_sub(1)..(y + 2).min(height) {
+                    for nx in x.saturating_sub(1)..(x + 2).min(width) {
+                        let neighbor = ny * width + nx;
+                        if labels[neighbor] == 0 && pixels[neighbor] >= FOREGROUND {
+                            labels[neighbor] = label;
+                            stack.push(neighbor);
+                        }
+                    }
+                }
+            }
+
+            areas.push(area);
+        }
+
+        Self { labels, areas }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    // A blob that's opaque up to `core` pixels from its center and fades out
+    // over `fade` more
+    fn soft_blob(x: u32, y: u32, center: (f32, f32), core: f32, fade: f32) -> u8 {
+        let distance = (x as f32 - center.0).hypot(y as f32 - center.1);
+        (255.0 * (1.0 - (distance - core) / fade)).clamp(0.0, 255.0) as u8
+    }
+
+    #[test]
+    fn dropped_blob_leaves_no_halo() {
+        // Two soft blobs over a faint background that connects them
+        let mut mask = GrayImage::from_fn(120, 40, |x, y| {
+            let large = soft_blob(x, y, (30.0, 20.0), 10.0, 20.0);
+            let small = soft_blob(x, y, (90.0, 20.0), 6.0, 20.0);
+            Luma([large.max(small).max(2)])
+        });
+        keep_largest(&mut mask, 1);
+
+        // The large blob keeps its core and soft edge
+        assert_eq!(mask.get_pixel(30, 20)[0], 255);
+        assert!(mask.get_pixel(48, 20)[0] > 0);
+        // The small blob is gone, edge included
+        assert_eq!(mask.get_pixel(90, 20)[0], 0);
+        assert_eq!(mask.get_pixel(70, 20)[0], 0);
+        assert_eq!(mask.get_pixel(110, 20)[0], 0);
+    }
+
+    // Opaque where the pattern has a `#`, one string per row
+    fn mask_from(rows: &[&str]) -> GrayImage {
+        GrayImage::from_fn(rows[0].len() as u32, rows.len() as u32, |x, y| {
+            Luma([if rows[y as usize].as_bytes()[x as usize] == b'#' {
+                255
+            } else {
+                0
+            }])
+        })
+    }
+
+    fn opaque_count(mask: &GrayImage) -> usize {
+        mask.as_raw().iter().filter(|&&value| value > 0).count()
+    }
+
+    #[test]
+    fn threshold_binarizes() {
+        let mut mask = GrayImage::from_raw(4, 1, vec![0, 127, 128, 200]).unwrap();
+        threshold(&mut mask, 128);
+        assert_eq!(mask.as_raw(), &[0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn erode_and_dilate_by_radius() {
+        let mut mask = mask_from(&[".....", ".....", "..#..", ".....", "....."]);
+        dilate(&mut mask, 1);
+        assert_eq!(opaque_count(&mask), 9);
+        erode(&mut mask, 1);
+        assert_eq!(opaque_count(&mask), 1);
+        assert_eq!(mask.get_pixel(2, 2)[0], 255);
+    }
+
+    #[test]
+    fn fills_enclosed_holes_only() {
+        let mut mask = mask_from(&["#####.", "#..#..", "#####.", "#..#..", "#..##."]);
+        fill_holes(&mut mask);
+        assert_eq!(mask.get_pixel(1, 1)[0], 255);
+        assert_eq!(mask.get_pixel(2, 1)[0], 255);
+        // Open to the border
+        assert_eq!(mask.get_pixel(1, 4)[0], 0);
+    }
+
+    #[test]
+    fn labels_8_connected_regions() {
+        let mask = mask_from(&["#...#", ".#..#", "....#", "##..."]);
+        let components = Components::label(&mask);
+        // Background, the diagonal pair, the column and the bottom row
+        assert_eq!(components.areas, vec![0, 2, 3, 2]);
+        assert_eq!(components.labels[0], components.labels[6]);
+    }
+
+    #[test]
+    fn soft_pixels_are_not_labeled() {
+        let mask = GrayImage::from_raw(3, 1, vec![255, 100, 255]).unwrap();
+        let components = Components::label(&mask);
+        assert_eq!(components.areas, vec![0, 1, 1]);
+        assert_eq!(components.labels[1], 0);
+    }
+
+    #[test]
+    fn removes_small_islands() {
+        let mut mask = mask_from(&["##...", "##...", ".....", "....#"]);
+        remove_islands(&mut mask, 2);
+        assert_eq!(opaque_count(&mask), 4);
+        assert_eq!(mask.get_pixel(4, 3)[0], 0);
+    }
+
+    #[test]
+    fn keeps_the_largest_regions() {
+        let mut mask = mask_from(&["###.#", ".....", "##..#"]);
+        keep_largest(&mut mask, 2);
+        assert_eq!(opaque_count(&mask), 5);
+        assert_eq!(mask.get_pixel(4, 0)[0], 0);
+        assert_eq!(mask.get_pixel(4, 2)[0], 0);
+    }
+
+    #[test]
+    fn empty_masks_stay_empty() {
+        let operations = [
+            MaskOperation::FillHoles,
+            MaskOperation::RemoveIslands { min_area: 10 },
+            MaskOperation::KeepLargest { count: 1 },
+            MaskOperation::Dilate { radius: 2 },
+            MaskOperation::Feather { sigma: 1.0 },
+        ];
+        let mut mask = GrayImage::new(8, 8);
+        apply_operations(&mut mask, &operations);
+        assert_eq!(opaque_count(&mask), 0);
+
+        let mut mask = GrayImage::new(0, 0);
+        apply_operations(&mut mask, &operations);
+        assert!(mask.is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_operations() {
+        assert!(validate_operations(&[MaskOperation::KeepLargest { count: 0 }]).is_err());
+        assert!(validate_operations(&[MaskOperation::Feather { sigma: f32::NAN }]).is_err());
+        assert!(validate_operations(&[MaskOperation::Feather { sigma: -1.0 }]).is_err());
+    }
+}
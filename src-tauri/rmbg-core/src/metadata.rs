@@ -0,0 +1,611 @@
+use image::ImageFormat;
+
+const INCHES_PER_METER: f64 = 39.3701;
+const CM_PER_INCH: f64 = 2.54;
+
+const X_RESOLUTION: u16 = 0x011A;
+const Y_RESOLUTION: u16 = 0x011B;
+const RESOLUTION_UNIT: u16 = 0x0128;
+const EXIF_IFD_POINTER: u16 = 0x8769;
+
+// EXIF tags copied to the output unless the full block is asked for: the
+// resolution, color space, capture date and camera
+const PRIMARY_TAGS: &[u16] = &[
+    0x010F, // Make
+    0x0110, // Model
+    X_RESOLUTION,
+    Y_RESOLUTION,
+    RESOLUTION_UNIT,
+    0x0132, // DateTime
+];
+const PHOTO_TAGS: &[u16] = &[
+    0x829A, // ExposureTime
+    0x829D, // FNumber
+    0x8827, // ISOSpeedRatings
+    0x9003, // DateTimeOriginal
+    0x9004, // DateTimeDigitized
+    0x9010, // OffsetTime
+    0x9011, // OffsetTimeOriginal
+    0x9012, // OffsetTimeDigitized
+    0x920A, // FocalLength
+    0xA001, // ColorSpace
+    0xA433, // LensMake
+    0xA434, // LensModel
+];
+
+// Color profile and metadata carried from an input image to its output
+#[derive(Debug, Clone, Default)]
+pub struct ImageMetadata {
+    pub icc_profile: Option<Vec<u8>>,
+    // Raw TIFF-structured EXIF block, with the orientation tag already reset
+    // since orientation is applied to the pixels when decoding
+    pub exif: Option<Vec<u8>>,
+    // Horizontal and vertical resolution in dots per inch
+    pub dpi: Option<(f64, f64)>,
+}
+
+// Reads the resolution stored in the container, falling back to the EXIF
+// resolution tags
+pub(crate) fn read_dpi(
+    bytes: &[u8],
+    format: ImageFormat,
+    exif: Option<&[u8]>,
+) -> Option<(f64, f64)> {
+    let container_dpi = match format {
+        ImageFormat::Jpeg => jfif_dpi(bytes),
+        ImageFormat::Png => png_dpi(bytes),
+        _ => None,
+    };
+
+    container_dpi.or_else(|| exif.and_then(exif_dpi))
+}
+
+fn jfif_dpi(bytes: &[u8]) -> Option<(f64, f64)> {
+    let mut pos = 2;
+
+    // Walk the marker segments up to the start of scan
+    while pos + 4 <= bytes.len() && bytes[pos] == 0xFF {
+        let marker = bytes[pos + 1];
+        let length = u16::from_be_bytes([bytes[pos + 2], bytes[pos + 3]]) as usize;
+        let data = bytes.get(pos + 4..pos + 2 + length)?;
+
+        if marker == 0xE0 && data.len() >= 12 && data.starts_with(b"JFIF\0") {
+            let x = u16::from_be_bytes([data[8], data[9]]) as f64;
+            let y = u16::from_be_bytes([data[10], data[11]]) as f64;
+            return match data[7] {
+                1 => Some((x, y)),
+                2 => Some((x * CM_PER_INCH, y * CM_PER_INCH)),
+                // Unit 0 only describes the pixel aspect ratio
+                _ => None,
+            };
+        }
+        if marker == 0xDA {
+            break;
+        }
+
+        pos += 2 + length;
+    }
+
+    None
+}
+
+fn png_dpi(bytes: &[u8]) -> Option<(f64, f64)> {
+    let mut pos = 8;
+
+    while pos + 8 <= bytes.len() {
+        let length = u32::from_be_bytes(bytes[pos..pos + 4].try_into().ok()?) as usize;
+        let chunk_type = &bytes[pos + 4..pos + 8];
+        let data = bytes.get(pos + 8..pos + 8 + length)?;
+
+        if chunk_type == b"pHYs" && data.len() == 9 {
+            // Unit 1 is pixels per meter, unit 0 is only an aspect ratio
+            if data[8] != 1 {
+                return None;
+            }
+            let x = u32::from_be_bytes(data[0..4].try_into().ok()?) as f64;
+            let y = u32::from_be_bytes(data[4..8].try_into().ok()?) as f64;
+            return Some((x / INCHES_PER_METER, y / INCHES_PER_METER));
+        }
+        if chunk_type == b"IDAT" {
+            break;
+        }
+
+        // Length, type, data and CRC
+        pos += 12 + length;
+    }
+
+    None
+}
+
+// Byte order aware reads from a TIFF-structured block, like EXIF
+struct Tiff<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> Tiff<'a> {
+    fn new(data: &'a [u8]) -> Option<Self> {
+        let big_endian = match data.get(0..4)? {
+            [0x49, 0x49, 42, 0] => false,
+            [0x4D, 0x4D, 0, 42] => true,
+            _ => return None,
+        };
+        Some(Self { data, big_endian })
+    }
+
+    fn u16(&self, pos: usize) -> Option<u16> {
+        let b: [u8; 2] = self.data.get(pos..pos.checked_add(2)?)?.try_into().ok()?;
+        Some(if self.big_endian {
+            u16::from_be_bytes(b)
+        } else {
+            u16::from_le_bytes(b)
+        })
+    }
+
+    fn u32(&self, pos: usize) -> Option<u32> {
+        let b: [u8; 4] = self.data.get(pos..pos.checked_add(4)?)?.try_into().ok()?;
+        Some(if self.big_endian {
+            u32::from_be_bytes(b)
+        } else {
+            u32::from_le_bytes(b)
+        })
+    }
+
+    fn rational(&self, pos: usize) -> Option<f64> {
+        let offset = self.u32(pos)? as usize;
+        let numerator = self.u32(offset)? as f64;
+        let denominator = self.u32(offset.checked_add(4)?)? as f64;
+        (denominator > 0.0).then(|| numerator / denominator)
+    }
+
+    // Offsets of the 12 byte entries of the IFD at `pos`
+    fn entries(&self, pos: usize) -> Option<impl Iterator<Item = usize>> {
+        let count = self.u16(pos)? as usize;
+        let end = pos + 2 + count * 12;
+        (end <= self.data.len()).then(|| (0..count).map(move |i| pos + 2 + i * 12))
+    }
+
+    // An entry's value bytes, stored in the entry itself when they fit in 4
+    // bytes and pointed to from there when they don't
+    fn value(&self, entry: usize) -> Option<&'a [u8]> {
+        let size = match self.u16(entry + 2)? {
+            1 | 2 | 6 | 7 => 1,
+            3 | 8 => 2,
+            4 | 9 | 11 => 4,
+            5 | 10 | 12 => 8,
+            _ => return None,
+        };
+        let size = (self.u32(entry + 4)? as usize).checked_mul(size)?;
+        if size <= 4 {
+            return self.data.get(entry + 8..entry + 8 + size);
+        }
+        let offset = self.u32(entry + 8)? as usize;
+        self.data.get(offset..offset.checked_add(size)?)
+    }
+}
+
+fn exif_dpi(exif: &[u8]) -> Option<(f64, f64)> {
+    let tiff = Tiff::new(exif)?;
+
+    let (mut x, mut y, mut unit) = (None, None, 2);
+    for entry in tiff.entries(tiff.u32(4)? as usize)? {
+        match tiff.u16(entry)? {
+            X_RESOLUTION => x = tiff.rational(entry + 8),
+            Y_RESOLUTION => y = tiff.rational(entry + 8),
+            RESOLUTION_UNIT => unit = tiff.u16(entry + 8)?,
+            _ => {}
+        }
+    }
+
+    let (x, y) = (x?, y?);
+    match unit {
+        2 => Some((x, y)),
+        3 => Some((x * CM_PER_INCH, y * CM_PER_INCH)),
+        _ => None,
+    }
+}
+
+// Rebuilds an EXIF block with only the tags on the allowlists, in the same
+// byte order. This leaves out GPS, maker notes, the embedded thumbnail and
+// anything else that could identify the owner beyond the camera and date.
+pub(crate) fn filter_exif(exif: &[u8]) -> Option<Vec<u8>> {
+    // Tag, type, count and value bytes
+    type Entry<'a> = (u16, u16, u32, &'a [u8]);
+
+    let tiff = Tiff::new(exif)?;
+
+    let kept = |ifd: usize, allowlist: &[u16]| -> Option<Vec<Entry>> {
+        let mut kept = Vec::new();
+        for entry in tiff.entries(ifd)? {
+            let tag = tiff.u16(entry)?;
+            if !allowlist.contains(&tag) {
+                continue;
+            }
+            // Entries with an unknown type or out of bounds value are dropped
+            if let Some(value) = tiff.value(entry) {
+                kept.push((tag, tiff.u16(entry + 2)?, tiff.u32(entry + 4)?, value));
+            }
+        }
+        Some(kept)
+    };
+
+    let ifd0 = tiff.u32(4)? as usize;
+    let mut primary = kept(ifd0, PRIMARY_TAGS)?;
+    let mut photo = tiff
+        .entries(ifd0)?
+        .find(|&entry| tiff.u16(entry) == Some(EXIF_IFD_POINTER))
+        .and_then(|entry| kept(tiff.u32(entry + 8)? as usize, PHOTO_TAGS))
+        .unwrap_or_default();
+    if primary.is_empty() && photo.is_empty() {
+        return None;
+    }
+
+    let put_u16 = |out: &mut Vec<u8>, value: u16| {
+        out.extend_from_slice(&if tiff.big_endian {
+            value.to_be_bytes()
+        } else {
+            value.to_le_bytes()
+        })
+    };
+    let put_u32 = |out: &mut Vec<u8>, value: u32| {
+        out.extend_from_slice(&if tiff.big_endian {
+            value.to_be_bytes()
+        } else {
+            value.to_le_bytes()
+        })
+    };
+
+    // Entries are sorted by tag, the pointer to the Exif IFD is filled in
+    // once the primary IFD's size is known
+    if !photo.is_empty() {
+        primary.push((EXIF_IFD_POINTER, 4, 1, &[]));
+    }
+    primary.sort_by_key(|&(tag, ..)| tag);
+    photo.sort_by_key(|&(tag, ..)| tag);
+
+    // Header, then the two IFDs, then the values that don't fit in entries
+    let ifd_size = |entries: &[Entry]| 2 + entries.len() * 12 + 4;
+    let photo_start = 8 + ifd_size(&primary);
+    let photo_size = if photo.is_empty() {
+        0
+    } else {
+        ifd_size(&photo)
+    };
+    let mut data_pos = photo_start + photo_size;
+
+    let mut out = exif[0..4].to_vec();
+    put_u32(&mut out, 8);
+    let mut data = Vec::new();
+    for entries in [&primary, &photo] {
+        if entries.is_empty() {
+            continue;
+        }
+        put_u16(&mut out, entries.len() as u16);
+        for &(tag, kind, count, value) in entries {
+            put_u16(&mut out, tag);
+            put_u16(&mut out, kind);
+            put_u32(&mut out, count);
+            if tag == EXIF_IFD_POINTER {
+                put_u32(&mut out, photo_start as u32);
+            } else if value.len() <= 4 {
+                let mut inline = [0; 4];
+                inline[..value.len()].copy_from_slice(value);
+                out.extend_from_slice(&inline);
+            } else {
+                put_u32(&mut out, data_pos as u32);
+                data.extend_from_slice(value);
+                // Values start on a word boundary
+                if value.len() % 2 == 1 {
+                    data.push(0);
+                }
+                data_pos += value.len() + value.len() % 2;
+            }
+        }
+        // No next IFD, which is where the thumbnail would have been
+        put_u32(&mut out, 0);
+    }
+    out.extend_from_slice(&data);
+
+    Some(out)
+}
+
+// Inserts a pHYs chunk right after IHDR, since the PNG encoder has no way to
+// set the resolution
+pub(crate) fn insert_png_dpi(png: &mut Vec<u8>, (x, y): (f64, f64)) {
+    // Signature (8 bytes) followed by the IHDR chunk (25 bytes)
+    const IHDR_END: usize = 33;
+    if png.len() < IHDR_END || &png[12..16] != b"IHDR" {
+        return;
+    }
+
+    let mut data = Vec::with_capacity(9);
+    data.extend_from_slice(&((x * INCHES_PER_METER).round() as u32).to_be_bytes());
+    data.extend_from_slice(&((y * INCHES_PER_METER).round() as u32).to_be_bytes());
+    data.push(1);
+
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(b"pHYs");
+    crc.update(&data);
+
+    let mut chunk = Vec::with_capacity(21);
+    chunk.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    chunk.extend_from_slice(b"pHYs");
+    chunk.extend_from_slice(&data);
+    chunk.extend_from_slice(&crc.finalize().to_be_bytes());
+
+    png.splice(IHDR_END..IHDR_END, chunk);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Tag, type, count and value of an IFD entry
+    type TestEntry = (u16, u16, u32, Vec<u8>);
+
+    struct Writer {
+        big_endian: bool,
+    }
+
+    impl Writer {
+        fn u16(&self, value: u16) -> [u8; 2] {
+            if self.big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            }
+        }
+
+        fn u32(&self, value: u32) -> [u8; 4] {
+            if self.big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            }
+        }
+
+        fn short(&self, tag: u16, value: u16) -> TestEntry {
+            (tag, 3, 1, self.u16(value).to_vec())
+        }
+
+        fn rational(&self, tag: u16, numerator: u32, denominator: u32) -> TestEntry {
+            let mut value = self.u32(numerator).to_vec();
+            value.extend_from_slice(&self.u32(denominator));
+            (tag, 5, 1, value)
+        }
+
+        fn ascii(&self, tag: u16, text: &str) -> TestEntry {
+            let mut value = text.as_bytes().to_vec();
+            value.push(0);
+            (tag, 2, value.len() as u32, value)
+        }
+
+        // A TIFF block with the primary IFD, an Exif IFD it points to when
+        // there are photo entries, and a second IFD chained after the first
+        fn tiff(&self, primary: &[TestEntry], photo: &[TestEntry], next: &[TestEntry]) -> Vec<u8> {
+            let ifd_size = |count: usize| 2 + count * 12 + 4;
+            let primary_count = primary.len() + !photo.is_empty() as usize;
+            let photo_start = 8 + ifd_size(primary_count);
+            let next_start = photo_start
+                + if photo.is_empty() {
+                    0
+                } else {
+                    ifd_size(photo.len())
+                };
+            let mut data_pos = next_start
+                + if next.is_empty() {
+                    0
+                } else {
+                    ifd_size(next.len())
+                };
+
+            let mut out = if self.big_endian {
+                b"MM".to_vec()
+            } else {
+                b"II".to_vec()
+            };
+            out.extend_from_slice(&self.u16(42));
+            out.extend_from_slice(&self.u32(8));
+
+            let mut data = Vec::new();
+            let mut primary = primary.to_vec();
+            if !photo.is_empty() {
+                primary.push((
+                    EXIF_IFD_POINTER,
+                    4,
+                    1,
+                    self.u32(photo_start as u32).to_vec(),
+                ));
+            }
+            let next_pointer = if next.is_empty() {
+                0
+            } else {
+                next_start as u32
+            };
+            for (entries, next_ifd) in [(&primary[..], next_pointer), (photo, 0), (next, 0)] {
+                if entries.is_empty() {
+                    continue;
+                }
+                out.extend_from_slice(&self.u16(entries.len() as u16));
+                for (tag, kind, count, value) in entries {
+                    out.extend_from_slice(&self.u16(*tag));
+                    out.extend_from_slice(&self.u16(*kind));
+                    out.extend_from_slice(&self.u32(*count));
+                    if value.len() <= 4 {
+                        let mut inline = [0; 4];
+                        inline[..value.len()].copy_from_slice(value);
+                        out.extend_from_slice(&inline);
+                    } else {
+                        out.extend_from_slice(&self.u32(data_pos as u32));
+                        data.extend_from_slice(value);
+                        data_pos += value.len();
+                    }
+                }
+                out.extend_from_slice(&self.u32(next_ifd));
+            }
+            out.extend_from_slice(&data);
+            out
+        }
+    }
+
+    // Tags of an IFD, in order
+    fn tags(tiff: &Tiff, ifd: usize) -> Vec<u16> {
+        tiff.entries(ifd)
+            .unwrap()
+            .map(|entry| tiff.u16(entry).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn reads_exif_resolution() {
+        for big_endian in [false, true] {
+            let w = Writer { big_endian };
+            let exif = w.tiff(
+                &[
+                    w.rational(X_RESOLUTION, 300, 1),
+                    w.rational(Y_RESOLUTION, 150, 1),
+                ],
+                &[],
+                &[],
+            );
+            assert_eq!(exif_dpi(&exif), Some((300.0, 150.0)));
+
+            let exif = w.tiff(
+                &[
+                    w.rational(X_RESOLUTION, 100, 1),
+                    w.rational(Y_RESOLUTION, 100, 1),
+                    w.short(RESOLUTION_UNIT, 3),
+                ],
+                &[],
+                &[],
+            );
+            assert_eq!(exif_dpi(&exif), Some((254.0, 254.0)));
+        }
+    }
+
+    #[test]
+    fn malformed_exif_is_ignored() {
+        let w = Writer { big_endian: false };
+        let exif = w.tiff(
+            &[
+                w.rational(X_RESOLUTION, 300, 1),
+                w.rational(Y_RESOLUTION, 300, 0),
+            ],
+            &[],
+            &[],
+        );
+        assert_eq!(exif_dpi(&exif), None);
+
+        for len in 0..exif.len() {
+            assert_eq!(exif_dpi(&exif[..len]), None);
+            let _ = filter_exif(&exif[..len]);
+        }
+        // An entry count running far past the end
+        let mut exif = exif;
+        exif[8..10].copy_from_slice(&u16::MAX.to_le_bytes());
+        assert_eq!(exif_dpi(&exif), None);
+        assert_eq!(filter_exif(&exif), None);
+        assert_eq!(exif_dpi(b"not exif"), None);
+    }
+
+    #[test]
+    fn filtered_exif_keeps_only_allowlisted_tags() {
+        for big_endian in [false, true] {
+            let w = Writer { big_endian };
+            let exif = w.tiff(
+                &[
+                    w.ascii(0x010F, "Canon"),
+                    w.rational(X_RESOLUTION, 300, 1),
+                    w.rational(Y_RESOLUTION, 300, 1),
+                    // GPS IFD pointer
+                    (0x8825, 4, 1, w.u32(0).to_vec()),
+                ],
+                &[
+                    w.short(0xA001, 1),
+                    w.ascii(0x9003, "2024:05:01 10:00:00"),
+                    // Maker note
+                    (0x927C, 7, 8, vec![1; 8]),
+                ],
+                // IFD1, the thumbnail
+                &[w.short(0x0103, 6)],
+            );
+
+            let filtered = filter_exif(&exif).unwrap();
+            let tiff = Tiff::new(&filtered).unwrap();
+            assert_eq!(tiff.big_endian, big_endian);
+
+            let primary = tiff.u32(4).unwrap() as usize;
+            assert_eq!(
+                tags(&tiff, primary),
+                [0x010F, X_RESOLUTION, Y_RESOLUTION, EXIF_IFD_POINTER]
+            );
+            let make = tiff.entries(primary).unwrap().next().unwrap();
+            assert_eq!(tiff.value(make), Some(&b"Canon\0"[..]));
+            assert_eq!(exif_dpi(&filtered), Some((300.0, 300.0)));
+
+            let pointer = tiff.entries(primary).unwrap().last().unwrap();
+            let photo = tiff.u32(pointer + 8).unwrap() as usize;
+            assert_eq!(tags(&tiff, photo), [0x9003, 0xA001]);
+            let date = tiff.entries(photo).unwrap().next().unwrap();
+            assert_eq!(tiff.value(date), Some(&b"2024:05:01 10:00:00\0"[..]));
+
+            // No IFD after the primary one
+            let next = primary + 2 + 4 * 12;
+            assert_eq!(tiff.u32(next), Some(0));
+        }
+    }
+
+    #[test]
+    fn exif_without_allowlisted_tags_is_dropped() {
+        let w = Writer { big_endian: false };
+        let exif = w.tiff(&[(0x8825, 4, 1, w.u32(0).to_vec())], &[], &[]);
+        assert_eq!(filter_exif(&exif), None);
+    }
+
+    #[test]
+    fn reads_jfif_resolution() {
+        let jfif = |unit: u8, x: u16, y: u16| {
+            let mut bytes = vec![0xFF, 0xD8, 0xFF, 0xE0, 0, 16];
+            bytes.extend_from_slice(b"JFIF\0");
+            bytes.extend_from_slice(&[1, 1, unit]);
+            bytes.extend_from_slice(&x.to_be_bytes());
+            bytes.extend_from_slice(&y.to_be_bytes());
+            bytes.extend_from_slice(&[0, 0, 0xFF, 0xDA]);
+            bytes
+        };
+        assert_eq!(jfif_dpi(&jfif(1, 300, 200)), Some((300.0, 200.0)));
+        assert_eq!(jfif_dpi(&jfif(2, 100, 100)), Some((254.0, 254.0)));
+        // Aspect ratio only
+        assert_eq!(jfif_dpi(&jfif(0, 1, 1)), None);
+        assert_eq!(jfif_dpi(&jfif(1, 300, 200)[..10]), None);
+    }
+
+    #[test]
+    fn png_resolution_round_trips() {
+        let mut png = crate::processor::encode_png(&image::DynamicImage::new_luma8(4, 4)).unwrap();
+        assert_eq!(png_dpi(&png), None);
+
+        insert_png_dpi(&mut png, (300.0, 72.0));
+        let (x, y) = png_dpi(&png).unwrap();
+        assert!((x - 300.0).abs() < 0.01 && (y - 72.0).abs() < 0.01);
+        // Still a valid PNG, the chunk's CRC included
+        assert!(image::load_from_memory(&png).is_ok());
+
+        // The container takes precedence over EXIF
+        let w = Writer { big_endian: false };
+        let exif = w.tiff(
+            &[
+                w.rational(X_RESOLUTION, 96, 1),
+                w.rational(Y_RESOLUTION, 96, 1),
+            ],
+            &[],
+            &[],
+        );
+        let (x, _) = read_dpi(&png, ImageFormat::Png, Some(&exif)).unwrap();
+        assert!((x - 300.0).abs() < 0.01);
+        assert_eq!(
+            read_dpi(b"", ImageFormat::Png, Some(&exif)),
+            Some((96.0, 96.0))
+        );
+    }
+}
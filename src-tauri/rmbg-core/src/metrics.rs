@@ -0,0 +1,266 @@
+use image::GrayImage;
+use serde::{Deserialize, Serialize};
+
+use crate::mask;
+
+// Mask values at or above this count as foreground when binarizing
+const FOREGROUND: u8 = 128;
+
+// Weight of precision against recall in the F-measure, the usual choice for
+// salient object detection
+const BETA_SQUARED: f64 = 0.3;
+
+// How far a boundary may be from the true one and still count as matching,
+// as a share of the image diagonal
+const BOUNDARY_TOLERANCE: f64 = 0.0075;
+
+// How closely a predicted mask matches the ground truth. All scores are
+// between 0 and 1, higher is better except for the MAE.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct MaskScores {
+    pub iou: f64,
+    pub f_measure: f64,
+    // Mean absolute error of the soft masks, the only score that sees
+    // partial transparency
+    pub mae: f64,
+    // F-measure of the mask outlines, how well the edges line up
+    pub boundary_f: f64,
+}
+
+impl MaskScores {
+    pub fn compare(predicted: &GrayImage, truth: &GrayImage) -> Self {
+        Self {
+            iou: iou(predicted, truth),
+            f_measure: f_measure(predicted, truth),
+            mae: mae(predicted, truth),
+            boundary_f: boundary_f(predicted, truth),
+        }
+    }
+
+    pub fn mean(scores: &[MaskScores]) -> Option<Self> {
+        if scores.is_empty() {
+            return None;
+        }
+        let count = scores.len() as f64;
+        let sum = scores.iter().fold(Self::default(), |sum, scores| Self {
+            iou: sum.iou + scores.iou,
+            f_measure: sum.f_measure + scores.f_measure,
+            mae: sum.mae + scores.mae,
+            boundary_f: sum.boundary_f + scores.boundary_f,
+        });
+        Some(Self {
+            iou: sum.iou / count,
+            f_measure: sum.f_measure / count,
+            mae: sum.mae / count,
+            boundary_f: sum.boundary_f / count,
+        })
+    }
+}
+
+// Intersection over union of the binarized masks. Two empty masks agree
+// completely.
+pub fn iou(predicted: &GrayImage, truth: &GrayImage) -> f64 {
+    let (mut intersection, mut union) = (0u64, 0u64);
+    for (&p, &t) in predicted.as_raw().iter().zip(truth.as_raw()) {
+        let (p, t) = (p >= FOREGROUND, t >= FOREGROUND);
+        intersection += (p && t) as u64;
+        union += (p || t) as u64;
+    }
+
+    if union == 0 {
+        1.0
+    } else {
+        intersection as f64 / union as f64
+    }
+}
+
+// F-measure of the binarized masks, weighting precision over recall
+pub fn f_measure(predicted: &GrayImage, truth: &GrayImage) -> f64 {
+    let (mut true_positives, mut predicted_count, mut truth_count) = (0u64, 0u64, 0u64);
+    for (&p, &t) in predicted.as_raw().iter().zip(truth.as_raw()) {
+        let (p, t) = (p >= FOREGROUND, t >= FOREGROUND);
+        true_positives += (p && t) as u64;
+        predicted_count += p as u64;
+        truth_count += t as u64;
+    }
+
+    if predicted_count == 0 && truth_count == 0 {
+        return 1.0;
+    }
+    if true_positives == 0 {
+        return 0.0;
+    }
+    let precision = true_positives as f64 / predicted_count as f64;
+    let recall = true_positives as f64 / truth_count as f64;
+    (1.0 + BETA_SQUARED) * precision * recall / (BETA_SQUARED * precision + recall)
+}
+
+pub fn mae(predicted: &GrayImage, truth: &GrayImage) -> f64 {
+    let sum: u64 = predicted
+        .as_raw()
+        .iter()
+        .zip(truth.as_raw())
+        .map(|(&p, &t)| p.abs_diff(t) as u64)
+        .sum();
+    sum as f64 / (predicted.as_raw().len().max(1) as f64 * 255.0)
+}
+
+// Boundary F-measure: the share of each outline lying within a small
+// tolerance of the other one
+pub fn boundary_f(predicted: &GrayImage, truth: &GrayImage) -> f64 {
+    let (width, height) = predicted.dimensions();
+    let diagonal = (width as f64).hypot(height as f64);
+    let tolerance = ((diagonal * BOUNDARY_TOLERANCE).round() as u32).max(1);
+
+    let predicted_boundary = boundary(predicted);
+    let truth_boundary = boundary(truth);
+    let mut predicted_band = predicted_boundary.clone();
+    mask::dilate(&mut predicted_band, tolerance);
+    let mut truth_band = truth_boundary.clone();
+    mask::dilate(&mut truth_band, tolerance);
+
+    let count = |outline: &GrayImage, band: &GrayImage| {
+        let mut matched = 0u64;
+        let mut total = 0u64;
+        for (&o, &b) in outline.as_raw().iter().zip(band.as_raw()) {
+            total += (o > 0) as u64;
+            matched += (o > 0 && b > 0) as u64;
+        }
+        (matched, total)
+    };
+    let (predicted_matched, predicted_total) = count(&predicted_boundary, &truth_band);
+    let (truth_matched, truth_total) = count(&truth_boundary, &predicted_band);
+
+    if predicted_total == 0 || truth_total == 0 {
+        return if predicted_total == truth_total {
+            1.0
+        } else {
+            0.0
+        };
+    }
+    let precision = predicted_matched as f64 / predicted_total as f64;
+    let recall = truth_matched as f64 / truth_total as f64;
+    if precision + recall == 0.0 {
+        0.0
+    } else {
+        2.0 * precision * recall / (precision + recall)
+    }
+}
+
+// Foreground pixels next to the background
+fn boundary(mask: &GrayImage) -> GrayImage {
+    let mut binary = mask.clone();
+    mask::threshold(&mut binary, FOREGROUND);
+    let mut eroded = binary.clone();
+    mask::erode(&mut eroded, 1);
+
+    for (pixel, &inner) in binary.iter_mut().zip(eroded.as_raw()) {
+        *pixel -= inner;
+    }
+    binary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    // A square of the given value on a black mask
+    fn square(size: u32, x: u32, y: u32, side: u32, value: u8) -> GrayImage {
+        GrayImage::from_fn(size, size, |px, py| {
+            let inside = (x..x + side).contains(&px) && (y..y + side).contains(&py);
+            Luma([if inside { value } else { 0 }])
+        })
+    }
+
+    #[test]
+    fn identical_masks_score_perfectly() {
+        let mask = square(64, 16, 16, 32, 255);
+        let scores = MaskScores::compare(&mask, &mask);
+        assert_eq!(scores.iou, 1.0);
+        assert_eq!(scores.f_measure, 1.0);
+        assert_eq!(scores.mae, 0.0);
+        assert_eq!(scores.boundary_f, 1.0);
+    }
+
+    #[test]
+    fn disjoint_masks_score_zero() {
+        let predicted = square(64, 0, 0, 16, 255);
+        let truth = square(64, 40, 40, 16, 255);
+        assert_eq!(iou(&predicted, &truth), 0.0);
+        assert_eq!(f_measure(&predicted, &truth), 0.0);
+        assert_eq!(boundary_f(&predicted, &truth), 0.0);
+    }
+
+    #[test]
+    fn partial_overlap() {
+        // Half the truth, and nothing else
+        let predicted = square(64, 0, 0, 32, 255);
+        let truth = GrayImage::from_fn(64, 64, |_, y| Luma([if y < 32 { 255 } else { 0 }]));
+        assert_eq!(iou(&predicted, &truth), 0.5);
+        // Precision 1, recall 0.5
+        let expected = 1.3 * 0.5 / (0.3 + 0.5);
+        assert!((f_measure(&predicted, &truth) - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn empty_masks_agree() {
+        let empty = GrayImage::new(32, 32);
+        assert_eq!(iou(&empty, &empty), 1.0);
+        assert_eq!(f_measure(&empty, &empty), 1.0);
+        assert_eq!(boundary_f(&empty, &empty), 1.0);
+
+        let mask = square(32, 8, 8, 8, 255);
+        assert_eq!(iou(&empty, &mask), 0.0);
+        assert_eq!(f_measure(&empty, &mask), 0.0);
+        assert_eq!(boundary_f(&empty, &mask), 0.0);
+    }
+
+    #[test]
+    fn soft_masks_are_binarized_except_for_mae() {
+        let soft = square(32, 8, 8, 8, 200);
+        let hard = square(32, 8, 8, 8, 255);
+        assert_eq!(iou(&soft, &hard), 1.0);
+        assert_eq!(f_measure(&soft, &hard), 1.0);
+        let expected = 64.0 * 55.0 / (32.0 * 32.0 * 255.0);
+        assert!((mae(&soft, &hard) - expected).abs() < 1e-9);
+
+        // Below the cutoff counts as background
+        let faint = square(32, 8, 8, 8, 100);
+        assert_eq!(iou(&faint, &hard), 0.0);
+    }
+
+    #[test]
+    fn boundary_tolerates_small_shifts() {
+        // The tolerance is 0.75% of the diagonal, 3 pixels here
+        let truth = square(300, 100, 100, 100, 255);
+        let close = square(300, 102, 100, 100, 255);
+        let far = square(300, 110, 100, 100, 255);
+        assert_eq!(boundary_f(&close, &truth), 1.0);
+        assert!(boundary_f(&far, &truth) < 0.6);
+    }
+
+    #[test]
+    fn averages_scores() {
+        assert!(MaskScores::mean(&[]).is_none());
+        let scores = [
+            MaskScores {
+                iou: 1.0,
+                f_measure: 0.5,
+                mae: 0.0,
+                boundary_f: 0.2,
+            },
+            MaskScores {
+                iou: 0.0,
+                f_measure: 0.5,
+                mae: 0.2,
+                boundary_f: 0.4,
+            },
+        ];
+        let mean = MaskScores::mean(&scores).unwrap();
+        assert_eq!(mean.iou, 0.5);
+        assert_eq!(mean.f_measure, 0.5);
+        assert!((mean.mae - 0.1).abs() < 1e-9);
+        assert!((mean.boundary_f - 0.3).abs() < 1e-9);
+    }
+}
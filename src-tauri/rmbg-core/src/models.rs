@@ -1,15 +1,86 @@
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 
+use crate::{Error, Result};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ModelInfo {
     pub id: String,
     pub name: String,
     pub description: String,
+    // Files of the full precision model
     pub files: Vec<ModelFile>,
+    // Smaller, lower precision exports of the same model
+    #[serde(default)]
+    pub variants: Vec<ModelVariant>,
     pub is_default: bool,
 }
 
+// Numeric precision of a model's weights. Lower precision models take less
+// memory and disk space and are often faster, at some loss of accuracy.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Precision {
+    #[default]
+    Fp32,
+    Fp16,
+    Int8,
+}
+
+impl Precision {
+    pub fn id(&self) -> &'static str {
+        match self {
+            Precision::Fp32 => "fp32",
+            Precision::Fp16 => "fp16",
+            Precision::Int8 => "int8",
+        }
+    }
+
+    pub fn from_id(id: &str) -> Option<Self> {
+        [Precision::Fp32, Precision::Fp16, Precision::Int8]
+            .into_iter()
+            .find(|precision| precision.id() == id)
+    }
+}
+
+impl std::fmt::Display for Precision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(self.id())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelVariant {
+    pub precision: Precision,
+    // Where the export comes from, which isn't always the checkpoint the
+    // full precision model was exported from
+    pub source: String,
+    pub files: Vec<ModelFile>,
+}
+
+impl ModelInfo {
+    // Precisions the model is available in, full precision first
+    pub fn precisions(&self) -> Vec<Precision> {
+        std::iter::once(Precision::Fp32)
+            .chain(self.variants.iter().map(|variant| variant.precision))
+            .collect()
+    }
+
+    pub fn variant_files(&self, precision: Precision) -> Result<&[ModelFile]> {
+        if precision == Precision::Fp32 {
+            return Ok(&self.files);
+        }
+        self.variants
+            .iter()
+            .find(|variant| variant.precision == precision)
+            .map(|variant| variant.files.as_slice())
+            .ok_or_else(|| Error::VariantNotFound {
+                model_id: self.id.clone(),
+                precision,
+            })
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ModelFile {
     pub name: String,
@@ -28,6 +99,7 @@ pub fn get_all_models() -> Vec<ModelInfo> {
                 url: "https://github.com/danielgatis/rembg/releases/download/v0.0.0/u2net.onnx".to_string(),
                 size_mb: 176,
             }],
+            variants: vec![],
             is_default: true,
         },
         ModelInfo {
@@ -39,6 +111,7 @@ pub fn get_all_models() -> Vec<ModelInfo> {
                 url: "https://github.com/danielgatis/rembg/releases/download/v0.0.0/u2netp.onnx".to_string(),
                 size_mb: 4,
             }],
+            variants: vec![],
             is_default: false,
         },
         ModelInfo {
@@ -50,6 +123,7 @@ pub fn get_all_models() -> Vec<ModelInfo> {
                 url: "https://github.com/danielgatis/rembg/releases/download/v0.0.0/u2net_human_seg.onnx".to_string(),
                 size_mb: 176,
             }],
+            variants: vec![],
             is_default: false,
         },
         ModelInfo {
@@ -61,6 +135,7 @@ pub fn get_all_models() -> Vec<ModelInfo> {
                 url: "https://github.com/danielgatis/rembg/releases/download/v0.0.0/u2net_cloth_seg.onnx".to_string(),
                 size_mb: 176,
             }],
+            variants: vec![],
             is_default: false,
         },
         ModelInfo {
@@ -72,6 +147,7 @@ pub fn get_all_models() -> Vec<ModelInfo> {
                 url: "https://github.com/danielgatis/rembg/releases/download/v0.0.0/silueta.onnx".to_string(),
                 size_mb: 43,
             }],
+            variants: vec![],
             is_default: false,
         },
         ModelInfo {
@@ -83,6 +159,7 @@ pub fn get_all_models() -> Vec<ModelInfo> {
                 url: "https://github.com/danielgatis/rembg/releases/download/v0.0.0/isnet-general-use.onnx".to_string(),
                 size_mb: 176,
             }],
+            variants: vec![],
             is_default: false,
         },
         ModelInfo {
@@ -94,6 +171,7 @@ pub fn get_all_models() -> Vec<ModelInfo> {
                 url: "https://github.com/danielgatis/rembg/releases/download/v0.0.0/isnet-anime.onnx".to_string(),
                 size_mb: 176,
             }],
+            variants: vec![],
             is_default: false,
         },
         ModelInfo {
@@ -112,6 +190,7 @@ pub fn get_all_models() -> Vec<ModelInfo> {
                     size_mb: 16,
                 },
             ],
+            variants: vec![],
             is_default: false,
         },
         ModelInfo {
@@ -123,6 +202,26 @@ pub fn get_all_models() -> Vec<ModelInfo> {
                 url: "https://github.com/danielgatis/rembg/releases/download/v0.0.0/BiRefNet-general-epoch_244.onnx".to_string(),
                 size_mb: 223,
             }],
+            variants: vec![
+                ModelVariant {
+                    precision: Precision::Fp16,
+                    source: "onnx-community/BiRefNet-ONNX, a 1024x1024 export of the upstream BiRefNet weights".to_string(),
+                    files: vec![ModelFile {
+                        name: "BiRefNet-general-fp16.onnx".to_string(),
+                        url: "https://huggingface.co/onnx-community/BiRefNet-ONNX/resolve/main/onnx/model_fp16.onnx".to_string(),
+                        size_mb: 112,
+                    }],
+                },
+                ModelVariant {
+                    precision: Precision::Int8,
+                    source: "onnx-community/BiRefNet-ONNX, a 1024x1024 export of the upstream BiRefNet weights".to_string(),
+                    files: vec![ModelFile {
+                        name: "BiRefNet-general-int8.onnx".to_string(),
+                        url: "https://huggingface.co/onnx-community/BiRefNet-ONNX/resolve/main/onnx/model_int8.onnx".to_string(),
+                        size_mb: 60,
+                    }],
+                },
+            ],
             is_default: false,
         },
         ModelInfo {
@@ -134,6 +233,26 @@ pub fn get_all_models() -> Vec<ModelInfo> {
                 url: "https://github.com/danielgatis/rembg/releases/download/v0.0.0/BiRefNet-general-bb_swin_v1_tiny-epoch_232.onnx".to_string(),
                 size_mb: 130,
             }],
+            variants: vec![
+                ModelVariant {
+                    precision: Precision::Fp16,
+                    source: "onnx-community/BiRefNet_lite-ONNX, a 1024x1024 export of the upstream BiRefNet lite weights".to_string(),
+                    files: vec![ModelFile {
+                        name: "BiRefNet-general-lite-fp16.onnx".to_string(),
+                        url: "https://huggingface.co/onnx-community/BiRefNet_lite-ONNX/resolve/main/onnx/model_fp16.onnx".to_string(),
+                        size_mb: 65,
+                    }],
+                },
+                ModelVariant {
+                    precision: Precision::Int8,
+                    source: "onnx-community/BiRefNet_lite-ONNX, a 1024x1024 export of the upstream BiRefNet lite weights".to_string(),
+                    files: vec![ModelFile {
+                        name: "BiRefNet-general-lite-int8.onnx".to_string(),
+                        url: "https://huggingface.co/onnx-community/BiRefNet_lite-ONNX/resolve/main/onnx/model_int8.onnx".to_string(),
+                        size_mb: 36,
+                    }],
+                },
+            ],
             is_default: false,
         },
         ModelInfo {
@@ -145,6 +264,7 @@ pub fn get_all_models() -> Vec<ModelInfo> {
                 url: "https://github.com/danielgatis/rembg/releases/download/v0.0.0/BiRefNet-portrait-epoch_150.onnx".to_string(),
                 size_mb: 223,
             }],
+            variants: vec![],
             is_default: false,
         },
         ModelInfo {
@@ -156,6 +276,7 @@ pub fn get_all_models() -> Vec<ModelInfo> {
                 url: "https://github.com/danielgatis/rembg/releases/download/v0.0.0/BiRefNet-DIS-epoch_590.onnx".to_string(),
                 size_mb: 223,
             }],
+            variants: vec![],
             is_default: false,
         },
         ModelInfo {
@@ -167,6 +288,7 @@ pub fn get_all_models() -> Vec<ModelInfo> {
                 url: "https://github.com/danielgatis/rembg/releases/download/v0.0.0/BiRefNet-HRSOD_DHU-epoch_115.onnx".to_string(),
                 size_mb: 223,
             }],
+            variants: vec![],
             is_default: false,
         },
         ModelInfo {
@@ -178,6 +300,7 @@ pub fn get_all_models() -> Vec<ModelInfo> {
                 url: "https://github.com/danielgatis/rembg/releases/download/v0.0.0/BiRefNet-COD-epoch_125.onnx".to_string(),
                 size_mb: 223,
             }],
+            variants: vec![],
             is_default: false,
         },
         ModelInfo {
@@ -189,6 +312,7 @@ pub fn get_all_models() -> Vec<ModelInfo> {
                 url: "https://github.com/danielgatis/rembg/releases/download/v0.0.0/BiRefNet-massive-epoch_240.onnx".to_string(),
                 size_mb: 223,
             }],
+            variants: vec![],
             is_default: false,
         },
     ]
@@ -205,26 +329,29 @@ pub fn get_model_by_id(id: &str) -> Option<ModelInfo> {
     get_all_models().into_iter().find(|m| m.id == id)
 }
 
-pub fn get_models_dir() -> anyhow::Result<PathBuf> {
-    let home = directories::BaseDirs::new()
-        .ok_or_else(|| anyhow::anyhow!("Could not determine home directory"))?;
+pub fn get_models_dir() -> Result<PathBuf> {
+    let home = directories::BaseDirs::new().ok_or(Error::HomeDirNotFound)?;
     let models_dir = home.home_dir().join(".u2net");
 
     if !models_dir.exists() {
-        std::fs::create_dir_all(&models_dir)?;
+        std::fs::create_dir_all(&models_dir).map_err(Error::file(&models_dir))?;
     }
 
     Ok(models_dir)
 }
 
-pub fn is_model_downloaded(model_id: &str) -> anyhow::Result<bool> {
-    let model = get_model_by_id(model_id)
-        .ok_or_else(|| anyhow::anyhow!("Model not found: {}", model_id))?;
+pub fn is_model_downloaded(model_id: &str) -> Result<bool> {
+    is_variant_downloaded(model_id, Precision::Fp32)
+}
+
+pub fn is_variant_downloaded(model_id: &str, precision: Precision) -> Result<bool> {
+    let model =
+        get_model_by_id(model_id).ok_or_else(|| Error::ModelNotFound(model_id.to_string()))?;
 
     let models_dir = get_models_dir()?;
 
     // Check if all files for this model exist
-    for file in &model.files {
+    for file in model.variant_files(precision)? {
         let file_path = models_dir.join(&file.name);
         if !file_path.exists() {
             return Ok(false);
@@ -234,16 +361,56 @@ pub fn is_model_downloaded(model_id: &str) -> anyhow::Result<bool> {
     Ok(true)
 }
 
+// Ids of the models downloaded in the given precision
+pub fn downloaded_models(precision: Precision) -> Result<Vec<String>> {
+    let mut ids = Vec::new();
+    for model in get_all_models() {
+        if model.precisions().contains(&precision) && is_variant_downloaded(&model.id, precision)? {
+            ids.push(model.id);
+        }
+    }
+    Ok(ids)
+}
+
+pub fn get_model_path(model_id: &str) -> Result<PathBuf> {
+    get_variant_path(model_id, Precision::Fp32)
+}
+
+pub fn get_variant_path(model_id: &str, precision: Precision) -> Result<PathBuf> {
+    let model =
+        get_model_by_id(model_id).ok_or_else(|| Error::ModelNotFound(model_id.to_string()))?;
+
+    let models_dir = get_models_dir()?;
+
+    // Get the model file path (use first file for single-file models)
+    let model_file = model
+        .variant_files(precision)?
+        .first()
+        .ok_or_else(|| Error::ModelHasNoFiles(model_id.to_string()))?;
+    let model_path = models_dir.join(&model_file.name);
+
+    if !model_path.exists() {
+        return Err(Error::ModelNotDownloaded {
+            model_id: model_id.to_string(),
+            path: model_path,
+        });
+    }
+
+    Ok(model_path)
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ModelStatus {
     pub model: ModelInfo,
     pub downloaded: bool,
     pub file_paths: Vec<String>,
+    // Precisions whose files are all downloaded
+    pub downloaded_precisions: Vec<Precision>,
 }
 
-pub fn get_model_status(model_id: &str) -> anyhow::Result<ModelStatus> {
-    let model = get_model_by_id(model_id)
-        .ok_or_else(|| anyhow::anyhow!("Model not found: {}", model_id))?;
+pub fn get_model_status(model_id: &str) -> Result<ModelStatus> {
+    let model =
+        get_model_by_id(model_id).ok_or_else(|| Error::ModelNotFound(model_id.to_string()))?;
 
     let models_dir = get_models_dir()?;
     let downloaded = is_model_downloaded(model_id)?;
@@ -254,9 +421,17 @@ pub fn get_model_status(model_id: &str) -> anyhow::Result<ModelStatus> {
         .map(|f| models_dir.join(&f.name).to_string_lossy().to_string())
         .collect();
 
+    let mut downloaded_precisions = Vec::new();
+    for precision in model.precisions() {
+        if is_variant_downloaded(model_id, precision)? {
+            downloaded_precisions.push(precision);
+        }
+    }
+
     Ok(ModelStatus {
         model,
         downloaded,
         file_paths,
+        downloaded_precisions,
     })
 }
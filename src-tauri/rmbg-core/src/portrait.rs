@@ -0,0 +1,151 @@
+use image::{Rgb, RgbImage, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+use crate::blur;
+use crate::{Error, Result};
+
+// Approximate sRGB gamma, the lens blur works on light intensity
+const GAMMA: f32 = 2.2;
+
+// Below this much background in a pixel's window the blurred background is
+// mostly guesswork, the pixel is covered by the subject anyway
+const MIN_BACKGROUND_WEIGHT: f32 = 1e-4;
+
+// Largest blur radius, well past what any background needs. The lens blur's
+// cost grows with the disc's area.
+const MAX_RADIUS: f32 = 500.0;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlurKind {
+    #[default]
+    Gaussian,
+    // Disc shaped blur where highlights spread into bokeh, like an out of
+    // focus camera lens
+    Lens,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BlurOptions {
+    pub kind: BlurKind,
+    // Blur radius in pixels, the lens blur's disc radius or about twice the
+    // Gaussian's sigma
+    pub radius: f32,
+    // Gaussian sigma applied to the mask so the subject fades into the
+    // blurred background instead of a hard edge
+    pub feather: f32,
+}
+
+impl Default for BlurOptions {
+    fn default() -> Self {
+        Self {
+            kind: BlurKind::Gaussian,
+            radius: 25.0,
+            feather: 3.0,
+        }
+    }
+}
+
+impl BlurOptions {
+    pub fn validate(&self) -> Result<()> {
+        if !(self.radius >= 1.0 && self.radius <= MAX_RADIUS) {
+            return Err(Error::InvalidOptions(format!(
+                "Blur radius must be between 1 and {}",
+                MAX_RADIUS
+            )));
+        }
+        if !(self.feather.is_finite() && self.feather >= 0.0) {
+            return Err(Error::InvalidOptions(
+                "Blur feather must be a non-negative number".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+// Composites a cutout over a blurred copy of its own background, like a
+// phone's portrait mode. The cutout's alpha is the (feathered) mask and its
+// color channels the original image.
+pub fn blur_background(cutout: &RgbaImage, options: &BlurOptions) -> RgbImage {
+    let (width, height) = (cutout.width() as usize, cutout.height() as usize);
+    let linear = options.kind == BlurKind::Lens;
+    let to_light = |c: u8| {
+        let c = c as f32 / 255.0;
+        if linear {
+            c.powf(GAMMA)
+        } else {
+            c
+        }
+    };
+
+    // Only the background is blurred, weighted by how much of each pixel is
+    // background, so the subject's colors don't bleed into it
+    let weighted: Vec<[f32; 4]> = cutout
+        .pixels()
+        .map(|p| {
+            let weight = 1.0 - p[3] as f32 / 255.0;
+            [
+                to_light(p[0]) * weight,
+                to_light(p[1]) * weight,
+                to_light(p[2]) * weight,
+                weight,
+            ]
+        })
+        .collect();
+
+    let blurred = match options.kind {
+        BlurKind::Gaussian => {
+            let sigma = ((options.radius / 2.0).round() as usize).max(1);
+            blur::gaussian_blur(&weighted, width, height, sigma)
+        }
+        BlurKind::Lens => blur::disc_blur(&weighted, width, height, options.radius as usize),
+    };
+
+    let mut output = RgbImage::new(cutout.width(), cutout.height());
+    for ((pixel, source), [r, g, b, weight]) in
+        output.pixels_mut().zip(cutout.pixels()).zip(blurred)
+    {
+        let alpha = source[3] as f32 / 255.0;
+        let mut color = [0u8; 3];
+
+        for (channel, background) in [r, g, b].into_iter().enumerate() {
+            let background = if weight > MIN_BACKGROUND_WEIGHT {
+                let light = background / weight;
+                if linear {
+                    light.powf(1.0 / GAMMA)
+                } else {
+                    light
+                }
+            } else {
+                source[channel] as f32 / 255.0
+            };
+            let subject = source[channel] as f32 / 255.0;
+            let value = subject * alpha + background * (1.0 - alpha);
+            color[channel] = (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+        }
+
+        *pixel = Rgb(color);
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_the_radius() {
+        let options = |radius| BlurOptions {
+            radius,
+            ..Default::default()
+        };
+        assert!(options(1.0).validate().is_ok());
+        assert!(options(MAX_RADIUS).validate().is_ok());
+        assert!(options(0.5).validate().is_err());
+        assert!(options(1e9).validate().is_err());
+        assert!(options(f32::INFINITY).validate().is_err());
+        assert!(options(f32::NAN).validate().is_err());
+    }
+}
@@ -0,0 +1,994 @@
+use half::f16;
+use image::codecs::png::PngEncoder;
+use image::metadata::Orientation;
+use image::{
+    DynamicImage, GenericImageView, GrayImage, ImageBuffer, ImageDecoder, ImageEncoder, ImageError,
+    ImageReader, Pixel, Rgba, RgbaImage,
+};
+use ndarray::{Array2, Array4, Axis};
+use ort::session::{Session, SessionOutputs};
+use ort::tensor::TensorElementType;
+use ort::value::Value;
+use serde::{Deserialize, Serialize};
+use std::io::Cursor;
+use std::path::Path;
+
+use crate::background::{self, Background};
+use crate::canvas::{self, CanvasOptions};
+use crate::crop::{self, CropOptions};
+use crate::effects::Effect;
+use crate::foreground;
+use crate::mask::{self, MaskOperation};
+use crate::metadata::{self, ImageMetadata};
+use crate::models::{self, Precision};
+use crate::portrait::{self, BlurOptions};
+use crate::resample;
+use crate::session::{self, Provider, SessionOptions};
+use crate::tiling::{self, TileBlender, TileOptions};
+use crate::upsampling::{self, Upsampling};
+use crate::{Error, Result};
+
+pub struct BackgroundRemover {
+    session: Session,
+    provider: Provider,
+    // Whether the model takes and returns half precision tensors, as FP16
+    // exports do. Quantized models keep float inputs and outputs.
+    half_precision: bool,
+    // Whether the model's batch dimension is dynamic, so several images can
+    // run through it at once
+    dynamic_batch: bool,
+    // Side length of the square image the model takes
+    input_size: u32,
+}
+
+impl BackgroundRemover {
+    pub fn new(model_path: &Path) -> Result<Self> {
+        Self::with_options(model_path, &SessionOptions::default())
+    }
+
+    pub fn with_options(model_path: &Path, options: &SessionOptions) -> Result<Self> {
+        let (session, provider) = session::create_session(model_path, options)?;
+        let input_type = session.inputs.first().map(|input| &input.input_type);
+        let half_precision =
+            input_type.and_then(|ty| ty.tensor_type()) == Some(TensorElementType::Float16);
+        let shape = input_type.and_then(|ty| ty.tensor_shape());
+        let dynamic_batch = shape
+            .and_then(|shape| shape.first())
+            .is_some_and(|&batch| batch < 0);
+        // Models exported for a fixed resolution, like BiRefNet and IS-Net at
+        // 1024, must be fed exactly that
+        let input_size = match shape.map(|shape| &shape[..]) {
+            Some(&[_, _, height, width]) if height > 0 && height == width => height as u32,
+            _ => DEFAULT_INPUT_SIZE,
+        };
+
+        Ok(Self {
+            session,
+            provider,
+            half_precision,
+            dynamic_batch,
+            input_size,
+        })
+    }
+
+    // Primary execution provider of the model, which runs the nodes it
+    // supports. Others fall back to the CPU, see `session::create_session`.
+    pub fn provider(&self) -> Provider {
+        self.provider
+    }
+
+    pub fn input_size(&self) -> u32 {
+        self.input_size
+    }
+
+    // How many images run through the model at once. Models exported with
+    // a fixed batch size of one take them one at a time.
+    pub fn batch_size(&self, options: &InferenceOptions) -> usize {
+        if self.dynamic_batch {
+            options.batch_size
+        } else {
+            1
+        }
+    }
+
+    pub fn remove_background(&mut self, input_image: &DynamicImage) -> Result<DynamicImage> {
+        let mask = self.predict_mask(input_image)?;
+
+        // Apply mask to original image
+        Self::apply_mask(input_image, &mask)
+    }
+
+    pub fn predict_mask(
+        &mut self,
+        input_image: &DynamicImage,
+    ) -> Result<ImageBuffer<image::Luma<u8>, Vec<u8>>> {
+        self.predict_mask_with_options(input_image, &InferenceOptions::default())
+    }
+
+    pub fn predict_mask_with_options(
+        &mut self,
+        input_image: &DynamicImage,
+        options: &InferenceOptions,
+    ) -> Result<ImageBuffer<image::Luma<u8>, Vec<u8>>> {
+        let output = self.infer(input_image, options.letterbox)?;
+        self.finish_mask(input_image, &output, options)
+    }
+
+    // Masks for several images, with the global passes run in batches where
+    // the model supports it
+    pub fn predict_masks_with_options(
+        &mut self,
+        input_images: &[&DynamicImage],
+        options: &InferenceOptions,
+    ) -> Result<Vec<GrayImage>> {
+        let batch_size = self.batch_size(options);
+        let mut masks = Vec::with_capacity(input_images.len());
+
+        for batch in input_images.chunks(batch_size) {
+            let outputs = self.infer_batch(batch, options.letterbox)?;
+            for (image, output) in batch.iter().zip(outputs) {
+                masks.push(self.finish_mask(image, &output, options)?);
+            }
+        }
+
+        Ok(masks)
+    }
+
+    // Turns the global pass' output into the full resolution mask, refining
+    // it with tiles and guided upsampling as configured
+    fn finish_mask(
+        &mut self,
+        input_image: &DynamicImage,
+        output: &ModelOutput,
+        options: &InferenceOptions,
+    ) -> Result<ImageBuffer<image::Luma<u8>, Vec<u8>>> {
+        let mask = self.scaled_mask(input_image, output, options)?;
+
+        match &options.upsampling {
+            Upsampling::Lanczos3 => Ok(mask),
+            Upsampling::GuidedFilter(guided) => {
+                Ok(upsampling::guided_filter(&mask, input_image, guided))
+            }
+        }
+    }
+
+    // Mask from the global pass and any tiles, scaled to the image size
+    fn scaled_mask(
+        &mut self,
+        input_image: &DynamicImage,
+        output: &ModelOutput,
+        options: &InferenceOptions,
+    ) -> Result<ImageBuffer<image::Luma<u8>, Vec<u8>>> {
+        let (orig_width, orig_height) = input_image.dimensions();
+        let filter = options.upsampling.resize_filter();
+
+        let range = value_range(output.content());
+        let mask =
+            Self::postprocess_output(output.content(), range, orig_width, orig_height, filter)?;
+
+        let Some(tiling) = &options.tiling else {
+            return Ok(mask);
+        };
+        let Some(tiles) = tiling::layout(orig_width, orig_height, tiling) else {
+            return Ok(mask);
+        };
+
+        // Tiles are normalized with the global pass' range rather than their
+        // own, a tile of pure background would otherwise be stretched into
+        // noise
+        let mut blender = TileBlender::new(orig_width, orig_height, tiling.overlap);
+        for batch in tiles.chunks(self.batch_size(options)) {
+            let crops: Vec<DynamicImage> = batch
+                .iter()
+                .map(|tile| input_image.crop_imm(tile.x, tile.y, tile.width, tile.height))
+                .collect();
+            let outputs = self.infer_batch(&crops.iter().collect::<Vec<_>>(), options.letterbox)?;
+            for (tile, output) in batch.iter().zip(outputs) {
+                let tile_mask = Self::postprocess_output(
+                    output.content(),
+                    range,
+                    tile.width,
+                    tile.height,
+                    filter,
+                )?;
+                blender.add(*tile, &tile_mask);
+            }
+        }
+
+        Ok(blender.finish(&mask))
+    }
+
+    // Runs the model, returning the first output channel at the model's
+    // resolution
+    fn infer(&mut self, image: &DynamicImage, letterbox: bool) -> Result<ModelOutput> {
+        let mut outputs = self.infer_batch(&[image], letterbox)?;
+        Ok(outputs.remove(0))
+    }
+
+    // Runs the model once over a batch of images stacked along the batch
+    // dimension, returning the first output channel of each
+    fn infer_batch(
+        &mut self,
+        images: &[&DynamicImage],
+        letterbox: bool,
+    ) -> Result<Vec<ModelOutput>> {
+        // Preprocess images
+        let mut tensors = Vec::with_capacity(images.len());
+        let mut placements = Vec::with_capacity(images.len());
+        for image in images {
+            if letterbox {
+                let (tensor, placement) = Self::preprocess_letterboxed(image, self.input_size);
+                tensors.push(tensor);
+                placements.push(Some(placement));
+            } else {
+                tensors.push(Self::preprocess_image(image, self.input_size));
+                placements.push(None);
+            }
+        }
+        let views: Vec<_> = tensors.iter().map(|tensor| tensor.view()).collect();
+        let input_tensor = ndarray::concatenate(Axis(0), &views)
+            .map_err(|e| Error::InvalidModelInput(e.to_string()))?;
+
+        // Run inference
+        let input_value = if self.half_precision {
+            Value::from_array(input_tensor.mapv(f16::from_f32))?.into_dyn()
+        } else {
+            Value::from_array(input_tensor)?.into_dyn()
+        };
+        let outputs: SessionOutputs = self.session.run(ort::inputs![input_value])?;
+
+        // Get the output tensor
+        let (shape, data) = match outputs[0].dtype().tensor_type() {
+            Some(TensorElementType::Float16) => {
+                let (shape, data) = outputs[0].try_extract_tensor::<f16>()?;
+                (
+                    shape.clone(),
+                    data.iter().map(|value| value.to_f32()).collect(),
+                )
+            }
+            _ => {
+                let (shape, data) = outputs[0].try_extract_tensor::<f32>()?;
+                (shape.clone(), data.to_vec())
+            }
+        };
+        let output = ndarray::ArrayD::from_shape_vec(
+            shape.iter().map(|&x| x as usize).collect::<Vec<_>>(),
+            data,
+        )
+        .and_then(|array| array.into_dimensionality::<ndarray::Ix4>())
+        .map_err(|e| Error::InvalidModelOutput(e.to_string()))?;
+
+        if output.dim().0 != images.len() {
+            return Err(Error::InvalidModelOutput(format!(
+                "expected a batch of {} outputs, got {}",
+                images.len(),
+                output.dim().0
+            )));
+        }
+        if output.dim().1 == 0 {
+            return Err(Error::InvalidModelOutput("no output channels".to_string()));
+        }
+
+        // Split the batch, taking the first channel if multi-channel
+        Ok(output
+            .outer_iter()
+            .zip(placements)
+            .map(|(mask, letterbox)| ModelOutput {
+                values: mask.index_axis(Axis(0), 0).to_owned(),
+                input_size: self.input_size,
+                letterbox,
+            })
+            .collect())
+    }
+
+    // Model input of the given size for an image stretched over the whole
+    // input
+    pub fn preprocess_image(image: &DynamicImage, size: u32) -> Array4<f32> {
+        let resized = resize_for_input(image, size, size);
+
+        to_tensor(&resized, [0.0; 3], Letterbox::full(size), size)
+    }
+
+    // Scales the image to fit the model input without distorting it and
+    // pads the rest with the image's average color, so padding doesn't
+    // introduce a hard edge the model could mistake for a subject boundary
+    fn preprocess_letterboxed(image: &DynamicImage, size: u32) -> (Array4<f32>, Letterbox) {
+        let (width, height) = image.dimensions();
+        let scale = size as f32 / width.max(height) as f32;
+        let content_width = ((width as f32 * scale).round() as u32).clamp(1, size);
+        let content_height = ((height as f32 * scale).round() as u32).clamp(1, size);
+
+        let resized = resize_for_input(image, content_width, content_height);
+
+        let mut sum = [0.0f64; 3];
+        for pixel in resized.pixels() {
+            for channel in 0..3 {
+                sum[channel] += pixel[channel] as f64;
+            }
+        }
+        let count = resized.pixels().len() as f64;
+        let fill = sum.map(|s| (s / count / 255.0) as f32);
+
+        let letterbox = Letterbox {
+            x: (size - content_width) / 2,
+            y: (size - content_height) / 2,
+            width: content_width,
+            height: content_height,
+        };
+
+        (to_tensor(&resized, fill, letterbox, size), letterbox)
+    }
+
+    // Normalizes model output values from the given range to a mask and
+    // scales it to the target size
+    pub fn postprocess_output(
+        channel_data: ndarray::ArrayView2<f32>,
+        (min, max): (f32, f32),
+        target_width: u32,
+        target_height: u32,
+        filter: image::imageops::FilterType,
+    ) -> Result<ImageBuffer<image::Luma<u8>, Vec<u8>>> {
+        let range = max - min;
+        let (height, width) = channel_data.dim();
+
+        // Normalize to [0, 1], resampling in floating point and only then
+        // quantizing keeps the mask's gradients smooth
+        let normalized: Vec<f32> = if range > 0.0 {
+            channel_data.iter().map(|&v| (v - min) / range).collect()
+        } else {
+            vec![0.0; width * height]
+        };
+
+        // Resize mask to original image size
+        let resized = resample::resize_plane(
+            &normalized,
+            width,
+            height,
+            target_width as usize,
+            target_height as usize,
+            filter,
+        );
+        let pixels = resized
+            .into_iter()
+            .map(|v| (v.clamp(0.0, 1.0) * 255.0).round() as u8)
+            .collect();
+
+        GrayImage::from_raw(target_width, target_height, pixels)
+            .ok_or_else(|| Error::InvalidModelOutput("mask size mismatch".to_string()))
+    }
+
+    pub fn apply_mask(
+        image: &DynamicImage,
+        mask: &ImageBuffer<image::Luma<u8>, Vec<u8>>,
+    ) -> Result<DynamicImage> {
+        let mut result = image.to_rgba8();
+
+        // Replace the alpha channel with the mask
+        for (pixel, &alpha) in result.chunks_exact_mut(4).zip(mask.as_raw()) {
+            pixel[3] = alpha;
+        }
+
+        Ok(DynamicImage::ImageRgba8(result))
+    }
+}
+
+// Models a `RemoverCache` keeps loaded by default. Enough for the three
+// kinds of images automatic model selection tells apart.
+const CACHED_MODELS: usize = 3;
+
+// Loaded models keyed by id, so each is only initialized once when images
+// go through several of them. Holds at most `capacity` models, dropping the
+// least recently used one to load another.
+pub struct RemoverCache {
+    precision: Precision,
+    session: SessionOptions,
+    capacity: usize,
+    // Least recently used first
+    removers: Vec<(String, BackgroundRemover)>,
+}
+
+impl RemoverCache {
+    pub fn new(precision: Precision, session: &SessionOptions) -> Self {
+        Self::with_capacity(precision, session, CACHED_MODELS)
+    }
+
+    pub fn with_capacity(precision: Precision, session: &SessionOptions, capacity: usize) -> Self {
+        Self {
+            precision,
+            session: session.clone(),
+            capacity: capacity.max(1),
+            removers: Vec::new(),
+        }
+    }
+
+    pub fn precision(&self) -> Precision {
+        self.precision
+    }
+
+    // The model's remover, loaded on first use
+    pub fn get(&mut self, model_id: &str) -> Result<&mut BackgroundRemover> {
+        match self.removers.iter().position(|(id, _)| id == model_id) {
+            Some(index) => {
+                let entry = self.removers.remove(index);
+                self.removers.push(entry);
+            }
+            None => {
+                let model_path = models::get_variant_path(model_id, self.precision)?;
+                let remover = BackgroundRemover::with_options(&model_path, &self.session)?;
+                if self.removers.len() >= self.capacity {
+                    self.removers.remove(0);
+                }
+                self.removers.push((model_id.to_string(), remover));
+            }
+        }
+
+        let (_, remover) = self.removers.last_mut().expect("just inserted");
+        Ok(remover)
+    }
+}
+
+// Side length of the square model input for models that don't fix it in
+// their input shape (standard input size for U2Net models)
+const DEFAULT_INPUT_SIZE: u32 = 320;
+
+// Where the image sits inside the model input, in input pixels
+#[derive(Debug, Clone, Copy)]
+struct Letterbox {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+impl Letterbox {
+    // The image stretched over the whole input
+    fn full(size: u32) -> Self {
+        Self {
+            x: 0,
+            y: 0,
+            width: size,
+            height: size,
+        }
+    }
+}
+
+struct ModelOutput {
+    values: Array2<f32>,
+    // Side length of the input the output was predicted from
+    input_size: u32,
+    letterbox: Option<Letterbox>,
+}
+
+impl ModelOutput {
+    // The part of the output covering the image. Outputs don't have to match
+    // the input resolution, so the letterbox is scaled to the output's.
+    fn content(&self) -> ndarray::ArrayView2<'_, f32> {
+        let Some(letterbox) = self.letterbox else {
+            return self.values.view();
+        };
+
+        let (height, width) = self.values.dim();
+        let scale = |value: u32, size: usize| value as usize * size / self.input_size as usize;
+        let x = scale(letterbox.x, width);
+        let y = scale(letterbox.y, height);
+        let content_width = scale(letterbox.width, width).clamp(1, width - x);
+        let content_height = scale(letterbox.height, height).clamp(1, height - y);
+
+        self.values
+            .slice(ndarray::s![y..y + content_height, x..x + content_width])
+    }
+}
+
+// Scales an image to the given size for the model input
+fn resize_for_input(image: &DynamicImage, width: u32, height: u32) -> image::RgbImage {
+    image
+        .resize_exact(width, height, image::imageops::FilterType::Lanczos3)
+        .to_rgb8()
+}
+
+// Lays out an image as a normalized (1, 3, size, size) model input, placing
+// it at the letterbox position on a background of the fill color
+fn to_tensor(
+    rgb: &image::RgbImage,
+    fill: [f32; 3],
+    letterbox: Letterbox,
+    size: u32,
+) -> Array4<f32> {
+    let size = size as usize;
+    let mut input = Array4::<f32>::zeros((1, 3, size, size));
+    let width = rgb.width() as usize;
+    let (x, y) = (letterbox.x as usize, letterbox.y as usize);
+
+    for (channel, value) in fill.into_iter().enumerate() {
+        let mut plane = input.index_axis_mut(Axis(0), 0);
+        let mut plane = plane.index_axis_mut(Axis(0), channel);
+        plane.fill(value);
+
+        // Normalize to [0, 1]
+        for (row, source) in plane
+            .slice_mut(ndarray::s![y.., x..x + width])
+            .rows_mut()
+            .into_iter()
+            .zip(rgb.as_raw().chunks_exact(width * 3))
+        {
+            for (value, pixel) in row.into_iter().zip(source.chunks_exact(3)) {
+                *value = pixel[channel] as f32 / 255.0;
+            }
+        }
+    }
+
+    input
+}
+
+// Min and max of a model output, used to normalize it to the full mask range
+fn value_range(output: ndarray::ArrayView2<f32>) -> (f32, f32) {
+    let min = output.iter().fold(f32::INFINITY, |a, &b| a.min(b));
+    let max = output.iter().fold(f32::NEG_INFINITY, |a, &b| a.max(b));
+    (min, max)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InferenceOptions {
+    // Refine the mask's edges with additional passes over overlapping tiles,
+    // for very large images
+    pub tiling: Option<TileOptions>,
+    // Keep the image's aspect ratio when scaling it to the model input,
+    // padding the rest, instead of stretching it
+    pub letterbox: bool,
+    // How the model's low resolution mask is scaled up to the image size
+    pub upsampling: Upsampling,
+    // Images, or tiles of one image, run through the model at once when it
+    // accepts batches. Larger batches are faster but take more memory.
+    pub batch_size: usize,
+}
+
+impl Default for InferenceOptions {
+    fn default() -> Self {
+        Self {
+            tiling: None,
+            letterbox: false,
+            upsampling: Upsampling::default(),
+            batch_size: 4,
+        }
+    }
+}
+
+impl InferenceOptions {
+    pub fn validate(&self) -> Result<()> {
+        if self.batch_size == 0 {
+            return Err(Error::InvalidOptions(
+                "Batch size must be at least 1".to_string(),
+            ));
+        }
+        if let Some(tiling) = &self.tiling {
+            tiling.validate()?;
+        }
+        self.upsampling.validate()
+    }
+}
+
+// Composites a cutout over a solid color, like rembg's `bgc` option
+pub fn apply_background_color(image: &DynamicImage, color: Rgba<u8>) -> DynamicImage {
+    let rgba = image.to_rgba8();
+    let (width, height) = rgba.dimensions();
+    let mut result = ImageBuffer::from_pixel(width, height, color);
+
+    for (x, y, pixel) in rgba.enumerate_pixels() {
+        result.get_pixel_mut(x, y).blend(pixel);
+    }
+
+    DynamicImage::ImageRgba8(result)
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProcessOptions {
+    // How the model is loaded, used when the options load it themselves
+    pub session: SessionOptions,
+    // Leave the input's color profile, EXIF and resolution out of the output
+    pub strip_metadata: bool,
+    // Copy the whole EXIF block, including GPS, maker notes and the
+    // thumbnail, rather than only the resolution, color space, capture date
+    // and camera
+    pub keep_all_exif: bool,
+    pub inference: InferenceOptions,
+    // Clean up the predicted mask before it's applied, in order
+    pub mask_operations: Vec<MaskOperation>,
+    // Swap subject and background after the mask operations
+    pub invert_mask: bool,
+    // Remove the old background's color spill from semi-transparent edges
+    pub decontaminate_colors: bool,
+    // Placed behind the subject after cropping and canvas fitting
+    pub background: Option<Background>,
+    // Shadows and reflections drawn beneath the subject, only when there's
+    // a background to draw them on
+    pub effects: Vec<Effect>,
+    // Crop the output to the subject instead of keeping the full canvas
+    pub crop: Option<CropOptions>,
+    // Place the subject on a fixed size canvas. Applied after cropping.
+    pub canvas: Option<CanvasOptions>,
+    pub output: OutputMode,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum OutputMode {
+    // The subject alone, on transparency or the configured background
+    #[default]
+    Cutout,
+    // The whole image, with the background blurred behind the sharp subject
+    BlurredBackground(BlurOptions),
+    // The whole image with the subject made transparent, for clean plates
+    // and inpainting
+    BackgroundOnly,
+}
+
+impl ProcessOptions {
+    pub fn validate(&self) -> Result<()> {
+        self.session.validate()?;
+        self.inference.validate()?;
+        mask::validate_operations(&self.mask_operations)?;
+        for effect in &self.effects {
+            effect.validate()?;
+        }
+        if let Some(crop) = &self.crop {
+            crop.validate()?;
+        }
+        if let Some(canvas) = &self.canvas {
+            canvas.validate()?;
+        }
+        if let OutputMode::BlurredBackground(blur) = &self.output {
+            blur.validate()?;
+        }
+        // The other modes always output the full image
+        if !matches!(self.output, OutputMode::Cutout)
+            && (self.crop.is_some() || self.canvas.is_some() || self.background.is_some())
+        {
+            return Err(Error::InvalidOptions(
+                "Only cutouts can be cropped, fitted to a canvas or placed on a background"
+                    .to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+// Decodes an in-memory image, sniffing the format from its contents since
+// there's no file extension to go by. The EXIF orientation is applied so the
+// pixels match what photo viewers show, phone cameras store portrait shots
+// sideways and only record the rotation as a tag.
+pub fn decode_image(bytes: &[u8]) -> Result<DynamicImage> {
+    decode_image_with_metadata(bytes).map(|(image, _)| image)
+}
+
+pub fn decode_image_with_metadata(bytes: &[u8]) -> Result<(DynamicImage, ImageMetadata)> {
+    let format = image::guess_format(bytes)?;
+    let mut decoder = ImageReader::with_format(Cursor::new(bytes), format).into_decoder()?;
+
+    // Metadata is best effort, a malformed block shouldn't stop the image
+    // from being processed
+    let icc_profile = decoder.icc_profile().ok().flatten();
+    let mut exif = decoder.exif_metadata().ok().flatten();
+    let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+
+    // The orientation is baked into the pixels below, so it must not be
+    // applied a second time by whoever views the output
+    if let Some(exif) = exif.as_mut() {
+        let _ = Orientation::remove_from_exif_chunk(exif);
+    }
+
+    let dpi = metadata::read_dpi(bytes, format, exif.as_deref());
+
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+
+    Ok((
+        image,
+        ImageMetadata {
+            icc_profile,
+            exif,
+            dpi,
+        },
+    ))
+}
+
+pub fn open_image(path: &Path) -> Result<DynamicImage> {
+    let bytes = std::fs::read(path).map_err(Error::file(path))?;
+    decode_image(&bytes)
+}
+
+pub fn encode_png(image: &DynamicImage) -> Result<Vec<u8>> {
+    encode_png_with_metadata(image, &ImageMetadata::default())
+}
+
+pub fn encode_png_with_metadata(image: &DynamicImage, metadata: &ImageMetadata) -> Result<Vec<u8>> {
+    let mut png = Vec::new();
+
+    let mut encoder = PngEncoder::new(&mut png);
+    if let Some(icc_profile) = &metadata.icc_profile {
+        encoder
+            .set_icc_profile(icc_profile.clone())
+            .map_err(ImageError::Unsupported)?;
+    }
+    if let Some(exif) = &metadata.exif {
+        encoder
+            .set_exif_metadata(exif.clone())
+            .map_err(ImageError::Unsupported)?;
+    }
+    image.write_with_encoder(encoder)?;
+
+    if let Some(dpi) = metadata.dpi {
+        metadata::insert_png_dpi(&mut png, dpi);
+    }
+
+    Ok(png)
+}
+
+pub fn process_image_bytes(
+    model_path: &Path,
+    input: &[u8],
+    options: &ProcessOptions,
+) -> Result<Vec<u8>> {
+    options.validate()?;
+
+    // Create background remover
+    let mut remover = BackgroundRemover::with_options(model_path, &options.session)?;
+
+    process_image_bytes_with(&mut remover, input, options)
+}
+
+// Like `process_image_bytes`, with an already loaded model so a batch of
+// images doesn't load it for each one. The options' session settings don't
+// apply here.
+pub fn process_image_bytes_with(
+    remover: &mut BackgroundRemover,
+    input: &[u8],
+    options: &ProcessOptions,
+) -> Result<Vec<u8>> {
+    options.validate()?;
+
+    // Decode input image
+    let (input_image, metadata) = decode_image_with_metadata(input)?;
+
+    process_decoded_image_with(remover, &input_image, &metadata, options)
+}
+
+// Like `process_image_bytes_with` for an image that's already decoded
+pub fn process_decoded_image_with(
+    remover: &mut BackgroundRemover,
+    input_image: &DynamicImage,
+    metadata: &ImageMetadata,
+    options: &ProcessOptions,
+) -> Result<Vec<u8>> {
+    let mask = remover.predict_mask_with_options(input_image, &options.inference)?;
+    finish_image(input_image, metadata, mask, options)
+}
+
+// Like `process_image_bytes_with` for several images, running the model over
+// them in batches where it supports that. Images that can't be decoded or
+// finished fail on their own, an inference failure fails the whole batch.
+pub fn process_image_bytes_batch_with(
+    remover: &mut BackgroundRemover,
+    inputs: &[&[u8]],
+    options: &ProcessOptions,
+) -> Result<Vec<Result<Vec<u8>>>> {
+    options.validate()?;
+
+    let decoded: Vec<_> = inputs
+        .iter()
+        .map(|input| decode_image_with_metadata(input))
+        .collect();
+    let images: Vec<&DynamicImage> = decoded
+        .iter()
+        .filter_map(|result| result.as_ref().ok())
+        .map(|(image, _)| image)
+        .collect();
+
+    let mut masks = remover
+        .predict_masks_with_options(&images, &options.inference)?
+        .into_iter();
+
+    Ok(decoded
+        .into_iter()
+        .map(|result| {
+            let (image, metadata) = result?;
+            let mask = masks.next().ok_or_else(|| {
+                Error::InvalidModelOutput("fewer masks than images in the batch".to_string())
+            })?;
+            finish_image(&image, &metadata, mask, options)
+        })
+        .collect())
+}
+
+// Everything after inference: refines the mask, renders the configured
+// output and encodes it
+pub fn finish_image(
+    input_image: &DynamicImage,
+    metadata: &ImageMetadata,
+    mut mask: GrayImage,
+    options: &ProcessOptions,
+) -> Result<Vec<u8>> {
+    mask::apply_operations(&mut mask, &options.mask_operations);
+    if options.invert_mask {
+        mask::invert(&mut mask);
+    }
+
+    let result = match &options.output {
+        OutputMode::Cutout => {
+            let cutout = cut_out(input_image, &mask, options)?;
+            DynamicImage::ImageRgba8(place_cutout(cutout, options)?)
+        }
+        OutputMode::BlurredBackground(blur) => {
+            // Feathering the mask is what softens the transition into the
+            // blurred background
+            mask::feather(&mut mask, blur.feather);
+            let cutout = cut_out(input_image, &mask, options)?;
+            DynamicImage::ImageRgb8(portrait::blur_background(&cutout, blur))
+        }
+        OutputMode::BackgroundOnly => {
+            mask::invert(&mut mask);
+            DynamicImage::ImageRgba8(cut_out(input_image, &mask, options)?)
+        }
+    };
+
+    encode_output(&result, metadata, options)
+}
+
+// Encodes a result, carrying over the input's metadata unless asked not to.
+// Only allowlisted EXIF tags are copied unless the options keep all of them.
+pub fn encode_output(
+    image: &DynamicImage,
+    metadata: &ImageMetadata,
+    options: &ProcessOptions,
+) -> Result<Vec<u8>> {
+    if options.strip_metadata {
+        encode_png(image)
+    } else if options.keep_all_exif {
+        encode_png_with_metadata(image, metadata)
+    } else {
+        let metadata = ImageMetadata {
+            exif: metadata.exif.as_deref().and_then(metadata::filter_exif),
+            ..metadata.clone()
+        };
+        encode_png_with_metadata(image, &metadata)
+    }
+}
+
+// Applies the mask to the original image
+fn cut_out(image: &DynamicImage, mask: &GrayImage, options: &ProcessOptions) -> Result<RgbaImage> {
+    let mut cutout = BackgroundRemover::apply_mask(image, mask)?.into_rgba8();
+    if options.decontaminate_colors {
+        foreground::decontaminate_colors(&mut cutout);
+    }
+    Ok(cutout)
+}
+
+// Crops the cutout, fits it to the canvas and places it on the background,
+// whichever of those are configured
+fn place_cutout(mut cutout: RgbaImage, options: &ProcessOptions) -> Result<RgbaImage> {
+    if let Some(crop_options) = &options.crop {
+        cutout = crop::crop_to_subject(&cutout, crop_options)?;
+    }
+
+    // A canvas background color goes behind the effects like any other
+    // background, so the canvas itself is left transparent
+    let background = options.background.clone().or_else(|| {
+        let color = options.canvas.as_ref()?.background?;
+        Some(Background::Color(color))
+    });
+
+    if let Some(canvas_options) = &options.canvas {
+        let canvas_options = CanvasOptions {
+            background: None,
+            ..canvas_options.clone()
+        };
+        cutout = canvas::fit_to_canvas(&cutout, &canvas_options);
+    }
+
+    match &background {
+        Some(background) => background::compose(&cutout, background, &options.effects),
+        None => Ok(cutout),
+    }
+}
+
+pub fn process_image(
+    model_path: &Path,
+    input_path: &Path,
+    output_path: &Path,
+    options: &ProcessOptions,
+) -> Result<()> {
+    let input = std::fs::read(input_path).map_err(Error::file(input_path))?;
+    let output = process_image_bytes(model_path, &input, options)?;
+    std::fs::write(output_path, output).map_err(Error::file(output_path))?;
+
+    Ok(())
+}
+
+pub fn process_image_with(
+    remover: &mut BackgroundRemover,
+    input_path: &Path,
+    output_path: &Path,
+    options: &ProcessOptions,
+) -> Result<()> {
+    let input = std::fs::read(input_path).map_err(Error::file(input_path))?;
+    let output = process_image_bytes_with(remover, &input, options)?;
+    std::fs::write(output_path, output).map_err(Error::file(output_path))?;
+
+    Ok(())
+}
+
+// Like `process_image_with` for several images, see
+// `process_image_bytes_batch_with`. Each pair is an input and output path.
+pub fn process_image_batch_with(
+    remover: &mut BackgroundRemover,
+    paths: &[(&Path, &Path)],
+    options: &ProcessOptions,
+) -> Result<Vec<Result<()>>> {
+    let inputs: Vec<Result<Vec<u8>>> = paths
+        .iter()
+        .map(|(input_path, _)| std::fs::read(input_path).map_err(Error::file(*input_path)))
+        .collect();
+    let readable: Vec<&[u8]> = inputs
+        .iter()
+        .filter_map(|input| input.as_deref().ok())
+        .collect();
+
+    let mut outputs = process_image_bytes_batch_with(remover, &readable, options)?.into_iter();
+
+    Ok(inputs
+        .into_iter()
+        .zip(paths)
+        .map(|(input, (_, output_path))| {
+            input?;
+            let output = outputs.next().expect("one output per readable image")?;
+            std::fs::write(output_path, output).map_err(Error::file(*output_path))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metadata_round_trips_through_png() {
+        // Resolution and orientation (rotate 90) in little endian EXIF
+        let mut exif = vec![0x49, 0x49, 42, 0, 8, 0, 0, 0, 1, 0];
+        exif.extend_from_slice(&[0x12, 0x01, 3, 0, 1, 0, 0, 0, 6, 0, 0, 0]);
+        exif.extend_from_slice(&[0, 0, 0, 0]);
+        let metadata = ImageMetadata {
+            icc_profile: Some(vec![7; 64]),
+            exif: Some(exif),
+            dpi: Some((300.0, 300.0)),
+        };
+        let image = DynamicImage::new_rgb8(4, 2);
+        let png = encode_png_with_metadata(&image, &metadata).unwrap();
+
+        let (decoded, read) = decode_image_with_metadata(&png).unwrap();
+        assert_eq!(read.icc_profile, metadata.icc_profile);
+        let (x, y) = read.dpi.unwrap();
+        assert!((x - 300.0).abs() < 0.01 && (y - 300.0).abs() < 0.01);
+        // The orientation is applied to the pixels and reset in the EXIF
+        assert_eq!(decoded.dimensions(), (2, 4));
+        let exif = read.exif.unwrap();
+        assert_eq!(&exif[18..20], &[1, 0]);
+
+        let png = encode_png(&image).unwrap();
+        let (_, read) = decode_image_with_metadata(&png).unwrap();
+        assert!(read.icc_profile.is_none() && read.exif.is_none() && read.dpi.is_none());
+    }
+
+    #[test]
+    fn validates_crop_options() {
+        let options = ProcessOptions {
+            crop: Some(CropOptions {
+                padding: crop::Padding::Percent(f32::NAN),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert!(options.validate().is_err());
+        assert!(ProcessOptions::default().validate().is_ok());
+    }
+}
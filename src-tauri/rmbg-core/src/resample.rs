@@ -0,0 +1,121 @@
+use image::imageops::FilterType;
+use std::f32::consts::PI;
+
+// Separable resampling of single channel f32 planes with the same filters as
+// `image::imageops::resize`, on plain slices so the inner loops vectorize.
+// Used for masks, which are scaled up to the full image size.
+pub(crate) fn resize_plane(
+    plane: &[f32],
+    width: usize,
+    height: usize,
+    new_width: usize,
+    new_height: usize,
+    filter: FilterType,
+) -> Vec<f32> {
+    if width == 0 || height == 0 {
+        return vec![0.0; new_width * new_height];
+    }
+    if (width, height) == (new_width, new_height) {
+        return plane.to_vec();
+    }
+
+    // Rows first, the columns pass then works on whole contiguous rows
+    let columns = taps(width, new_width, filter);
+    let mut rows_resized = vec![0.0; new_width * height];
+    for (row, output) in plane
+        .chunks_exact(width)
+        .zip(rows_resized.chunks_exact_mut(new_width))
+    {
+        for (value, (start, weights)) in output.iter_mut().zip(&columns) {
+            *value = row[*start..*start + weights.len()]
+                .iter()
+                .zip(weights)
+                .map(|(v, w)| v * w)
+                .sum();
+        }
+    }
+
+    let rows = taps(height, new_height, filter);
+    let mut output = vec![0.0; new_width * new_height];
+    for (output_row, (start, weights)) in output.chunks_exact_mut(new_width).zip(&rows) {
+        for (offset, weight) in weights.iter().enumerate() {
+            let row_start = (start + offset) * new_width;
+            let row = &rows_resized[row_start..row_start + new_width];
+            for (value, v) in output_row.iter_mut().zip(row) {
+                *value += v * weight;
+            }
+        }
+    }
+
+    output
+}
+
+// First source index and normalized weights for each output index
+fn taps(length: usize, new_length: usize, filter: FilterType) -> Vec<(usize, Vec<f32>)> {
+    let (kernel, support): (fn(f32) -> f32, f32) = match filter {
+        FilterType::Nearest => (|_| 1.0, 0.0),
+        FilterType::Triangle => (triangle, 1.0),
+        FilterType::CatmullRom => (catmull_rom, 2.0),
+        FilterType::Gaussian => (gaussian, 3.0),
+        FilterType::Lanczos3 => (lanczos3, 3.0),
+    };
+
+    // When shrinking, the kernel is stretched to cover every source pixel
+    let ratio = length as f32 / new_length as f32;
+    let scale = ratio.max(1.0);
+    let source_support = support * scale;
+
+    (0..new_length)
+        .map(|i| {
+            let center = (i as f32 + 0.5) * ratio;
+            let left = ((center - source_support).floor().max(0.0) as usize).min(length - 1);
+            let right = ((center + source_support).ceil() as usize).clamp(left + 1, length);
+
+            let mut weights: Vec<f32> = (left..right)
+                .map(|j| kernel((j as f32 - (center - 0.5)) / scale))
+                .collect();
+            let sum: f32 = weights.iter().sum();
+            for weight in &mut weights {
+                *weight /= sum;
+            }
+            (left, weights)
+        })
+        .collect()
+}
+
+fn sinc(t: f32) -> f32 {
+    if t == 0.0 {
+        1.0
+    } else {
+        (t * PI).sin() / (t * PI)
+    }
+}
+
+fn lanczos3(x: f32) -> f32 {
+    if x.abs() < 3.0 {
+        sinc(x) * sinc(x / 3.0)
+    } else {
+        0.0
+    }
+}
+
+fn triangle(x: f32) -> f32 {
+    (1.0 - x.abs()).max(0.0)
+}
+
+fn catmull_rom(x: f32) -> f32 {
+    let a = x.abs();
+    let k = if a < 1.0 {
+        9.0 * a.powi(3) - 15.0 * a.powi(2) + 6.0
+    } else if a < 2.0 {
+        -3.0 * a.powi(3) + 15.0 * a.powi(2) - 24.0 * a + 12.0
+    } else {
+        0.0
+    };
+    k / 6.0
+}
+
+fn gaussian(x: f32) -> f32 {
+    // Standard deviation of 0.5, normalization doesn't matter here
+    (-2.0 * x * x).exp()
+}
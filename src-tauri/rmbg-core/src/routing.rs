@@ -0,0 +1,527 @@
+use image::imageops::FilterType;
+use image::{DynamicImage, GrayImage, Luma, Rgb, RgbImage};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use crate::mask::Components;
+use crate::models::{self, Precision};
+use crate::processor::{self, BackgroundRemover, ProcessOptions, RemoverCache};
+use crate::session::{Provider, SessionOptions};
+use crate::{Error, Result};
+
+// Model id that picks a model for each image
+pub const AUTO_MODEL_ID: &str = "auto";
+
+// Size images are scaled down to before looking at them. Sampled with the
+// nearest filter, averaging would smooth a photo's grain into flat areas.
+const SAMPLE_SIZE: u32 = 256;
+
+// Illustrations are mostly flat fills in few colors. Share of neighboring
+// pixels with about the same color, and most color bins covering 95% of the
+// image, for an image to count as one.
+const ILLUSTRATION_FLATNESS: f32 = 0.6;
+const ILLUSTRATION_COLORS: usize = 64;
+
+// Smallest face, as a share of the image, for it to count as a portrait
+const FACE_MIN_AREA: f32 = 0.02;
+
+// Skin regions with more dark spots than this are textured material, like
+// wood grain, rather than a face
+const FACE_MAX_FEATURES: usize = 8;
+
+// Models to use for each kind of image, best first. The cloth, camouflage
+// and SAM models don't do general background removal and are never picked.
+const PHOTO_MODELS: [&str; 9] = [
+    "birefnet-general",
+    "birefnet-massive",
+    "birefnet-hrsod",
+    "birefnet-dis",
+    "birefnet-general-lite",
+    "isnet-general-use",
+    "u2net",
+    "silueta",
+    "u2netp",
+];
+// The general BiRefNet models handle people better than the older human
+// segmentation model, which only comes before the U2Net family
+const PORTRAIT_MODELS: [&str; 7] = [
+    "birefnet-portrait",
+    "birefnet-general",
+    "birefnet-massive",
+    "birefnet-hrsod",
+    "birefnet-dis",
+    "birefnet-general-lite",
+    "u2net_human_seg",
+];
+const ILLUSTRATION_MODELS: [&str; 1] = ["isnet-anime"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageKind {
+    Photo,
+    Portrait,
+    Illustration,
+}
+
+impl ImageKind {
+    pub fn id(&self) -> &'static str {
+        match self {
+            ImageKind::Photo => "photo",
+            ImageKind::Portrait => "portrait",
+            ImageKind::Illustration => "illustration",
+        }
+    }
+}
+
+impl std::fmt::Display for ImageKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(self.id())
+    }
+}
+
+// Guesses what an image shows: flat fills in few colors make an
+// illustration, a face a portrait. Cheap and rough, good enough to pick
+// between specialized models. Anything unsure is a photo, which goes to a
+// general model.
+pub fn classify(image: &DynamicImage) -> ImageKind {
+    let sample = image
+        .resize(SAMPLE_SIZE, SAMPLE_SIZE, FilterType::Nearest)
+        .to_rgb8();
+
+    if flatness(&sample) >= ILLUSTRATION_FLATNESS && dominant_colors(&sample) <= ILLUSTRATION_COLORS
+    {
+        ImageKind::Illustration
+    } else if has_face(&sample) {
+        ImageKind::Portrait
+    } else {
+        ImageKind::Photo
+    }
+}
+
+// Share of horizontally neighboring pixels with nearly the same color
+fn flatness(image: &RgbImage) -> f32 {
+    let (mut flat, mut total) = (0u32, 0u32);
+    for row in image.rows() {
+        let row: Vec<&Rgb<u8>> = row.collect();
+        for pair in row.windows(2) {
+            let close = (0..3).all(|c| pair[0][c].abs_diff(pair[1][c]) <= 3);
+            flat += close as u32;
+            total += 1;
+        }
+    }
+    if total == 0 {
+        0.0
+    } else {
+        flat as f32 / total as f32
+    }
+}
+
+// Number of 4-bit-per-channel color bins needed to cover 95% of the pixels
+fn dominant_colors(image: &RgbImage) -> usize {
+    let mut bins = HashMap::new();
+    for pixel in image.pixels() {
+        let bin = (pixel[0] >> 4, pixel[1] >> 4, pixel[2] >> 4);
+        *bins.entry(bin).or_insert(0u32) += 1;
+    }
+
+    let mut counts: Vec<u32> = bins.into_values().collect();
+    counts.sort_unstable_by(|a, b| b.cmp(a));
+    let target = image.pixels().len() as f32 * 0.95;
+    let mut covered = 0.0;
+    for (index, count) in counts.iter().enumerate() {
+        covered += *count as f32;
+        if covered >= target {
+            return index + 1;
+        }
+    }
+    counts.len()
+}
+
+// Looks for a face: a sizable skin region shaped like one, with a pair of
+// dark spots side by side in its upper half where the eyes are. Skin colored
+// products like leather, wood or cardboard have the color but not the eyes.
+fn has_face(image: &RgbImage) -> bool {
+    let (width, height) = image.dimensions();
+    let skin = GrayImage::from_fn(width, height, |x, y| {
+        Luma([if is_skin(image.get_pixel(x, y)) {
+            255
+        } else {
+            0
+        }])
+    });
+    let components = Components::label(&skin);
+    let min_area = (width * height) as f32 * FACE_MIN_AREA;
+
+    let mut bounds = vec![Bounds::default(); components.areas.len()];
+    for (index, &label) in components.labels.iter().enumerate() {
+        if label != 0 {
+            bounds[label as usize].add(index as u32 % width, index as u32 / width);
+        }
+    }
+
+    (1..components.areas.len())
+        .filter(|&label| components.areas[label] as f32 >= min_area)
+        .any(|label| {
+            let bounds = bounds[label];
+            let (region_width, region_height) = (bounds.width(), bounds.height());
+            let aspect = region_height as f32 / region_width as f32;
+            let fill = components.areas[label] as f32 / (region_width * region_height) as f32;
+
+            // Faces are taller than wide, a neck can make them up to twice
+            // as tall
+            (0.9..=2.2).contains(&aspect)
+                && fill >= 0.5
+                && has_eyes(image, &components.labels, label as u32, bounds)
+        })
+}
+
+// Whether a skin region has two dark holes at about the same height, one on
+// each side of its middle, in its upper half
+fn has_eyes(image: &RgbImage, labels: &[u32], label: u32, bounds: Bounds) -> bool {
+    let width = image.width();
+    let (region_width, region_height) = (bounds.width(), bounds.height());
+
+    // Everything in the region's box that isn't the region, labeled so the
+    // parts not touching the box's edge are holes in the region
+    let outside = GrayImage::from_fn(region_width, region_height, |x, y| {
+        let index = ((bounds.y0 + y) * width + bounds.x0 + x) as usize;
+        Luma([if labels[index] == label { 0 } else { 255 }])
+    });
+    let holes = Components::label(&outside);
+
+    let mut skin_luma = (0.0, 0u32);
+    let mut features = vec![Feature::default(); holes.areas.len()];
+    for y in 0..region_height {
+        for x in 0..region_width {
+            let index = (y * region_width + x) as usize;
+            let luma = luma(image.get_pixel(bounds.x0 + x, bounds.y0 + y));
+            match holes.labels[index] {
+                0 => skin_luma = (skin_luma.0 + luma, skin_luma.1 + 1),
+                hole => features[hole as usize].add(x, y, luma, region_width, region_height),
+            }
+        }
+    }
+    let skin_luma = skin_luma.0 / skin_luma.1.max(1) as f32;
+
+    let box_area = (region_width * region_height) as f32;
+    let eyes: Vec<&Feature> = features
+        .iter()
+        .skip(1)
+        .filter(|feature| {
+            let area = feature.area as f32;
+            let (_, y) = feature.center();
+            !feature.touches_edge
+                && area >= box_area * 0.002
+                && area <= box_area * 0.05
+                && feature.mean_luma() < skin_luma * 0.75
+                && y >= region_height as f32 * 0.15
+                && y <= region_height as f32 * 0.6
+        })
+        .collect();
+    if eyes.len() < 2 || eyes.len() > FACE_MAX_FEATURES {
+        return false;
+    }
+
+    let middle = region_width as f32 / 2.0;
+    eyes.iter().enumerate().any(|(index, left)| {
+        eyes[index + 1..].iter().any(|right| {
+            let (lx, ly) = left.center();
+            let (rx, ry) = right.center();
+            let spacing = (lx - rx).abs() / region_width as f32;
+            (lx - middle) * (rx - middle) < 0.0
+                && (ly - ry).abs() <= region_height as f32 * 0.1
+                && (0.2..=0.7).contains(&spacing)
+        })
+    })
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bounds {
+    x0: u32,
+    y0: u32,
+    x1: u32,
+    y1: u32,
+}
+
+impl Default for Bounds {
+    fn default() -> Self {
+        Self {
+            x0: u32::MAX,
+            y0: u32::MAX,
+            x1: 0,
+            y1: 0,
+        }
+    }
+}
+
+impl Bounds {
+    fn add(&mut self, x: u32, y: u32) {
+        self.x0 = self.x0.min(x);
+        self.y0 = self.y0.min(y);
+        self.x1 = self.x1.max(x);
+        self.y1 = self.y1.max(y);
+    }
+
+    fn width(&self) -> u32 {
+        self.x1 - self.x0 + 1
+    }
+
+    fn height(&self) -> u32 {
+        self.y1 - self.y0 + 1
+    }
+}
+
+// A hole in a skin region
+#[derive(Debug, Clone, Default)]
+struct Feature {
+    area: u32,
+    x_sum: f32,
+    y_sum: f32,
+    luma_sum: f32,
+    touches_edge: bool,
+}
+
+impl Feature {
+    fn add(&mut self, x: u32, y: u32, luma: f32, width: u32, height: u32) {
+        self.area += 1;
+        self.x_sum += x as f32;
+        self.y_sum += y as f32;
+        self.luma_sum += luma;
+        self.touches_edge |= x == 0 || y == 0 || x + 1 == width || y + 1 == height;
+    }
+
+    fn center(&self) -> (f32, f32) {
+        let area = self.area.max(1) as f32;
+        (self.x_sum / area, self.y_sum / area)
+    }
+
+    fn mean_luma(&self) -> f32 {
+        self.luma_sum / self.area.max(1) as f32
+    }
+}
+
+fn luma(pixel: &Rgb<u8>) -> f32 {
+    let [r, g, b] = pixel.0;
+    0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32
+}
+
+// Kovac et al.'s rule for skin in daylight, narrowed by the usual chroma
+// ranges for skin in YCbCr
+fn is_skin(pixel: &Rgb<u8>) -> bool {
+    let [r, g, b] = pixel.0;
+    let spread = r.max(g).max(b) - r.min(g).min(b);
+    let rgb = r > 95 && g > 40 && b > 20 && spread > 15 && r.abs_diff(g) > 15 && r > g && r > b;
+
+    let (r, g, b) = (r as f32, g as f32, b as f32);
+    let cb = 128.0 - 0.168_736 * r - 0.331_264 * g + 0.5 * b;
+    let cr = 128.0 + 0.5 * r - 0.418_688 * g - 0.081_312 * b;
+    rgb && (77.0..=127.0).contains(&cb) && (133.0..=173.0).contains(&cr)
+}
+
+// The best downloaded model for a kind of image
+pub fn recommend_model(kind: ImageKind, downloaded: &HashSet<String>) -> Option<&'static str> {
+    let specialized: &[&'static str] = match kind {
+        ImageKind::Photo => &[],
+        ImageKind::Portrait => &PORTRAIT_MODELS,
+        ImageKind::Illustration => &ILLUSTRATION_MODELS,
+    };
+    specialized
+        .iter()
+        .chain(&PHOTO_MODELS)
+        .copied()
+        .find(|id| downloaded.contains(*id))
+}
+
+// Which model an image went to and why
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Route {
+    pub kind: ImageKind,
+    pub model_id: String,
+    pub provider: Provider,
+}
+
+// Picks a model for each image among the downloaded ones, keeping the models
+// it has loaded for the images after
+pub struct AutoRemover {
+    downloaded: HashSet<String>,
+    removers: RemoverCache,
+}
+
+impl AutoRemover {
+    pub fn new(precision: Precision, session: &SessionOptions) -> Result<Self> {
+        session.validate()?;
+
+        Ok(Self {
+            downloaded: models::downloaded_models(precision)?.into_iter().collect(),
+            removers: RemoverCache::new(precision, session),
+        })
+    }
+
+    // The model for an image, loaded on first use
+    pub fn remover_for(
+        &mut self,
+        image: &DynamicImage,
+    ) -> Result<(ImageKind, String, &mut BackgroundRemover)> {
+        let kind = classify(image);
+        let Some(model_id) = recommend_model(kind, &self.downloaded) else {
+            // Reports the default model as the one to download
+            let default = models::get_default_model();
+            return Err(
+                models::get_variant_path(&default.id, self.removers.precision())
+                    .err()
+                    .unwrap_or(Error::ModelNotFound(AUTO_MODEL_ID.to_string())),
+            );
+        };
+
+        Ok((kind, model_id.to_string(), self.removers.get(model_id)?))
+    }
+}
+
+// `processor::process_image_bytes_with` with the model picked for the image
+pub fn process_image_bytes(
+    auto: &mut AutoRemover,
+    input: &[u8],
+    options: &ProcessOptions,
+) -> Result<(Vec<u8>, Route)> {
+    options.validate()?;
+
+    let (image, metadata) = processor::decode_image_with_metadata(input)?;
+    let (kind, model_id, remover) = auto.remover_for(&image)?;
+    let output = processor::process_decoded_image_with(remover, &image, &metadata, options)?;
+
+    let route = Route {
+        kind,
+        model_id,
+        provider: remover.provider(),
+    };
+    Ok((output, route))
+}
+
+pub fn process_image(
+    auto: &mut AutoRemover,
+    input_path: &Path,
+    output_path: &Path,
+    options: &ProcessOptions,
+) -> Result<Route> {
+    let input = std::fs::read(input_path).map_err(Error::file(input_path))?;
+    let (output, route) = process_image_bytes(auto, &input, options)?;
+    std::fs::write(output_path, output).map_err(Error::file(output_path))?;
+
+    Ok(route)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Deterministic noise, so synthetic images have a photo's grain
+    fn noise(seed: u32) -> impl FnMut() -> i16 {
+        let mut state = seed;
+        move || {
+            state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (state >> 24) as i16 / 16 - 8
+        }
+    }
+
+    fn shade(color: [u8; 3], noise: i16) -> Rgb<u8> {
+        Rgb(color.map(|c| (c as i16 + noise).clamp(0, 255) as u8))
+    }
+
+    fn inside_ellipse(x: u32, y: u32, cx: f32, cy: f32, rx: f32, ry: f32) -> bool {
+        let (dx, dy) = ((x as f32 - cx) / rx, (y as f32 - cy) / ry);
+        dx * dx + dy * dy <= 1.0
+    }
+
+    const SKIN: [u8; 3] = [205, 150, 120];
+    const BACKDROP: [u8; 3] = [70, 90, 120];
+
+    fn face() -> DynamicImage {
+        let mut noise = noise(1);
+        DynamicImage::ImageRgb8(RgbImage::from_fn(256, 256, |x, y| {
+            let eye = inside_ellipse(x, y, 105.0, 105.0, 10.0, 6.0)
+                || inside_ellipse(x, y, 151.0, 105.0, 10.0, 6.0);
+            let color = if eye {
+                [40, 30, 30]
+            } else if inside_ellipse(x, y, 128.0, 128.0, 60.0, 80.0) {
+                SKIN
+            } else {
+                BACKDROP
+            };
+            shade(color, noise())
+        }))
+    }
+
+    #[test]
+    fn face_is_a_portrait() {
+        assert_eq!(classify(&face()), ImageKind::Portrait);
+    }
+
+    #[test]
+    fn skin_colored_product_is_a_photo() {
+        let mut noise = noise(2);
+        let cardboard = RgbImage::from_fn(256, 256, |x, y| {
+            let inside = (48..208).contains(&x) && (40..216).contains(&y);
+            shade(if inside { SKIN } else { BACKDROP }, noise())
+        });
+        assert_eq!(
+            classify(&DynamicImage::ImageRgb8(cardboard)),
+            ImageKind::Photo
+        );
+    }
+
+    #[test]
+    fn wood_grain_is_a_photo() {
+        let mut noise = noise(3);
+        let wood = RgbImage::from_fn(256, 256, |x, y| {
+            let inside = inside_ellipse(x, y, 128.0, 128.0, 70.0, 90.0);
+            let grain = (x / 3 + y / 17) % 9 == 0;
+            let color = match (inside, grain) {
+                (true, true) => [90, 55, 35],
+                (true, false) => SKIN,
+                _ => BACKDROP,
+            };
+            shade(color, noise())
+        });
+        assert_eq!(classify(&DynamicImage::ImageRgb8(wood)), ImageKind::Photo);
+    }
+
+    #[test]
+    fn flat_drawing_is_an_illustration() {
+        let drawing = RgbImage::from_fn(256, 256, |x, y| {
+            if inside_ellipse(x, y, 128.0, 128.0, 60.0, 80.0) {
+                Rgb(SKIN)
+            } else {
+                Rgb([250, 250, 250])
+            }
+        });
+        assert_eq!(
+            classify(&DynamicImage::ImageRgb8(drawing)),
+            ImageKind::Illustration
+        );
+    }
+
+    #[test]
+    fn recommends_the_best_downloaded_model() {
+        let downloaded = |ids: &[&str]| ids.iter().map(|id| id.to_string()).collect();
+
+        // A general BiRefNet model beats the older human segmentation model
+        let models = downloaded(&["u2net_human_seg", "birefnet-general"]);
+        assert_eq!(
+            recommend_model(ImageKind::Portrait, &models),
+            Some("birefnet-general")
+        );
+
+        let models = downloaded(&["u2net_human_seg", "u2net"]);
+        assert_eq!(
+            recommend_model(ImageKind::Portrait, &models),
+            Some("u2net_human_seg")
+        );
+        assert_eq!(recommend_model(ImageKind::Photo, &models), Some("u2net"));
+
+        // Specialized models are never picked for other kinds of images
+        let models = downloaded(&["isnet-anime", "u2net_human_seg"]);
+        assert_eq!(recommend_model(ImageKind::Photo, &models), None);
+        assert_eq!(recommend_model(ImageKind::Photo, &HashSet::new()), None);
+    }
+}
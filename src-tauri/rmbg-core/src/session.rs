@@ -0,0 +1,184 @@
+use ort::execution_providers::{
+    CPUExecutionProvider, CUDAExecutionProvider, CoreMLExecutionProvider,
+    DirectMLExecutionProvider, ExecutionProvider, OpenVINOExecutionProvider,
+    TensorRTExecutionProvider, XNNPACKExecutionProvider,
+};
+use ort::session::builder::GraphOptimizationLevel;
+use ort::session::Session;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::{Error, Result};
+
+// ONNX Runtime execution providers we know how to set up. Only the CPU
+// provider is always there, the others need ONNX Runtime built with them
+// (see the crate's Cargo features) and the matching hardware and drivers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    Cpu,
+    Xnnpack,
+    OpenVino,
+    Cuda,
+    TensorRt,
+    CoreMl,
+    DirectMl,
+}
+
+impl Provider {
+    pub const ALL: [Provider; 7] = [
+        Provider::Cpu,
+        Provider::Xnnpack,
+        Provider::OpenVino,
+        Provider::Cuda,
+        Provider::TensorRt,
+        Provider::CoreMl,
+        Provider::DirectMl,
+    ];
+
+    // Name used on the command line and in settings
+    pub fn id(&self) -> &'static str {
+        match self {
+            Provider::Cpu => "cpu",
+            Provider::Xnnpack => "xnnpack",
+            Provider::OpenVino => "openvino",
+            Provider::Cuda => "cuda",
+            Provider::TensorRt => "tensorrt",
+            Provider::CoreMl => "coreml",
+            Provider::DirectMl => "directml",
+        }
+    }
+
+    pub fn from_id(id: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|provider| provider.id() == id)
+    }
+
+    fn execution_provider(&self, options: &SessionOptions) -> Box<dyn ExecutionProvider> {
+        match self {
+            Provider::Cpu => {
+                Box::new(CPUExecutionProvider::default().with_arena_allocator(options.memory_arena))
+            }
+            Provider::Xnnpack => Box::new(XNNPACKExecutionProvider::default()),
+            Provider::OpenVino => Box::new(OpenVINOExecutionProvider::default()),
+            Provider::Cuda => Box::new(CUDAExecutionProvider::default()),
+            Provider::TensorRt => Box::new(TensorRTExecutionProvider::default()),
+            Provider::CoreMl => Box::new(CoreMLExecutionProvider::default()),
+            Provider::DirectMl => Box::new(DirectMLExecutionProvider::default()),
+        }
+    }
+}
+
+impl std::fmt::Display for Provider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(self.id())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SessionOptions {
+    // Providers to try, in order of preference. Providers that aren't
+    // available are skipped, the CPU provider is always the last resort.
+    pub providers: Vec<Provider>,
+    // Threads used within an operator, ONNX Runtime picks when unset
+    pub intra_threads: Option<usize>,
+    // Threads used to run independent operators in parallel. Setting this
+    // enables parallel execution.
+    pub inter_threads: Option<usize>,
+    // Keep freed memory around for reuse, which is faster but holds on to
+    // the peak memory use. Only affects the CPU provider.
+    pub memory_arena: bool,
+    // Plan allocations from the first run and reuse the plan, which speeds
+    // up runs with the same input shape at the cost of memory for the plan
+    pub memory_pattern: bool,
+}
+
+impl Default for SessionOptions {
+    fn default() -> Self {
+        Self {
+            providers: Vec::new(),
+            intra_threads: None,
+            inter_threads: None,
+            memory_arena: true,
+            memory_pattern: true,
+        }
+    }
+}
+
+impl SessionOptions {
+    pub fn validate(&self) -> Result<()> {
+        if self.intra_threads == Some(0) || self.inter_threads == Some(0) {
+            return Err(Error::InvalidOptions(
+                "Thread counts must be at least 1".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+// Providers the loaded ONNX Runtime was built with. They may still fail to
+// start, for instance without a GPU or its drivers.
+pub fn available_providers() -> Vec<Provider> {
+    let options = SessionOptions::default();
+    Provider::ALL
+        .into_iter()
+        .filter(|provider| {
+            *provider == Provider::Cpu
+                || provider
+                    .execution_provider(&options)
+                    .is_available()
+                    .unwrap_or(false)
+        })
+        .collect()
+}
+
+// Loads a model, returning the session and its primary provider: the first
+// of the requested providers that registered, or the CPU if none did. ONNX
+// Runtime assigns each node to the first registered provider that can run
+// it, so the primary provider runs most of the graph but nodes it can't run
+// fall back to the ones after it, the CPU last. That partial fallback isn't
+// visible here and isn't reported.
+pub(crate) fn create_session(
+    model_path: &Path,
+    options: &SessionOptions,
+) -> Result<(Session, Provider)> {
+    options.validate()?;
+
+    let mut builder =
+        Session::builder()?.with_optimization_level(GraphOptimizationLevel::Level3)?;
+    if let Some(threads) = options.intra_threads {
+        builder = builder.with_intra_threads(threads)?;
+    }
+    if let Some(threads) = options.inter_threads {
+        builder = builder
+            .with_parallel_execution(true)?
+            .with_inter_threads(threads)?;
+    }
+    builder = builder.with_memory_pattern(options.memory_pattern)?;
+
+    // Register the providers one by one rather than handing ONNX Runtime
+    // the whole list, so we know which one registered first
+    let mut primary = None;
+    for provider in &options.providers {
+        if *provider == Provider::Cpu {
+            continue;
+        }
+        let execution_provider = provider.execution_provider(options);
+        if !execution_provider.is_available().unwrap_or(false) {
+            continue;
+        }
+        if execution_provider.register(&mut builder).is_ok() && primary.is_none() {
+            primary = Some(*provider);
+        }
+    }
+
+    // The CPU provider is registered last so it only picks up what the
+    // others can't run
+    Provider::Cpu
+        .execution_provider(options)
+        .register(&mut builder)
+        .map_err(ort::Error::from)?;
+
+    let session = builder.commit_from_file(model_path)?;
+    Ok((session, primary.unwrap_or(Provider::Cpu)))
+}
@@ -0,0 +1,214 @@
+use image::{GrayImage, Luma};
+use serde::{Deserialize, Serialize};
+
+use crate::mask;
+use crate::{Error, Result};
+
+// Global mask values between these count as the subject's uncertain edge,
+// where the tiles' finer detail is used
+const EDGE_LOW: u8 = 16;
+const EDGE_HIGH: u8 = 240;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TileOptions {
+    // Tile edge length in pixels of the original image. Each tile is scaled
+    // to the model's input size on its own, so smaller tiles keep more
+    // detail but take more inference runs.
+    pub tile_size: u32,
+    // Pixels shared between neighboring tiles, blended to hide the seams
+    pub overlap: u32,
+}
+
+impl Default for TileOptions {
+    fn default() -> Self {
+        Self {
+            tile_size: 1024,
+            overlap: 128,
+        }
+    }
+}
+
+impl TileOptions {
+    pub fn validate(&self) -> Result<()> {
+        if self.tile_size < 64 {
+            return Err(Error::InvalidOptions(
+                "Tile size must be at least 64 pixels".to_string(),
+            ));
+        }
+        if self.overlap >= self.tile_size / 2 {
+            return Err(Error::InvalidOptions(
+                "Tile overlap must be less than half the tile size".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+// Overlapping tiles covering the whole image. `None` if the image fits in a
+// single tile, in which case tiling wouldn't add anything.
+pub(crate) fn layout(width: u32, height: u32, options: &TileOptions) -> Option<Vec<Tile>> {
+    if width <= options.tile_size && height <= options.tile_size {
+        return None;
+    }
+
+    let columns = positions(width, options);
+    let rows = positions(height, options);
+
+    let mut tiles = Vec::with_capacity(columns.len() * rows.len());
+    for &(y, tile_height) in &rows {
+        for &(x, tile_width) in &columns {
+            tiles.push(Tile {
+                x,
+                y,
+                width: tile_width,
+                height: tile_height,
+            });
+        }
+    }
+
+    Some(tiles)
+}
+
+// Start and length of the tiles along one axis, spread evenly so each pair
+// of neighbors overlaps by at least the configured amount
+fn positions(length: u32, options: &TileOptions) -> Vec<(u32, u32)> {
+    if length <= options.tile_size {
+        return vec![(0, length)];
+    }
+
+    let step = options.tile_size - options.overlap;
+    let count = (length - options.overlap - 1) / step + 1;
+    let last = length - options.tile_size;
+
+    (0..count)
+        .map(|i| {
+            let start = (i as u64 * last as u64 / (count as u64 - 1)) as u32;
+            (start, options.tile_size)
+        })
+        .collect()
+}
+
+// Accumulates tile masks with weights that ramp down across the overlaps
+pub(crate) struct TileBlender {
+    width: u32,
+    overlap: u32,
+    values: Vec<f32>,
+    weights: Vec<f32>,
+}
+
+impl TileBlender {
+    pub fn new(width: u32, height: u32, overlap: u32) -> Self {
+        let len = width as usize * height as usize;
+        Self {
+            width,
+            overlap,
+            values: vec![0.0; len],
+            weights: vec![0.0; len],
+        }
+    }
+
+    pub fn add(&mut self, tile: Tile, mask: &GrayImage) {
+        for (x, y, value) in mask.enumerate_pixels() {
+            // Ramp from the tile's edges, the weights of overlapping tiles
+            // fade into each other. Pixels are never weighted zero, so the
+            // image border keeps full coverage.
+            let distance = x.min(y).min(tile.width - 1 - x).min(tile.height - 1 - y);
+            let weight = ((distance + 1) as f32 / (self.overlap + 1) as f32).min(1.0);
+
+            let index = (tile.y + y) as usize * self.width as usize + (tile.x + x) as usize;
+            self.values[index] += value[0] as f32 * weight;
+            self.weights[index] += weight;
+        }
+    }
+
+    // Uses the blended tiles along the global mask's uncertain edge and the
+    // global mask everywhere else. The tiles lack the context to tell the
+    // subject from background objects, the global pass lacks the detail.
+    pub fn finish(self, global: &GrayImage) -> GrayImage {
+        let (width, height) = global.dimensions();
+
+        // Widen the edge band a little and soften it, so the switch between
+        // the two masks isn't visible
+        let radius = (width.max(height) / 200).max(2);
+        let mut band = GrayImage::from_fn(width, height, |x, y| {
+            let value = global.get_pixel(x, y)[0];
+            Luma([if (EDGE_LOW..=EDGE_HIGH).contains(&value) {
+                255
+            } else {
+                0
+            }])
+        });
+        mask::dilate(&mut band, radius);
+        mask::feather(&mut band, radius as f32 / 2.0);
+
+        let mut output = GrayImage::new(width, height);
+        for (index, pixel) in output.iter_mut().enumerate() {
+            let global_value = global.as_raw()[index] as f32;
+            let tiled_value = if self.weights[index] > 0.0 {
+                self.values[index] / self.weights[index]
+            } else {
+                global_value
+            };
+
+            let detail = band.as_raw()[index] as f32 / 255.0;
+            *pixel = (tiled_value * detail + global_value * (1.0 - detail)).round() as u8;
+        }
+
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(tile_size: u32, overlap: u32) -> TileOptions {
+        TileOptions { tile_size, overlap }
+    }
+
+    #[test]
+    fn positions_cover_the_axis_with_overlap() {
+        for length in [1025, 1500, 2048, 4000, 10_000] {
+            let options = options(1024, 128);
+            let positions = positions(length, &options);
+
+            assert_eq!(positions[0].0, 0);
+            let (last_start, last_length) = positions[positions.len() - 1];
+            assert_eq!(last_start + last_length, length);
+            for pair in positions.windows(2) {
+                let (start, tile_length) = pair[0];
+                assert!(start + tile_length >= pair[1].0 + options.overlap);
+            }
+        }
+    }
+
+    #[test]
+    fn short_axes_take_one_tile() {
+        assert_eq!(positions(500, &options(1024, 128)), vec![(0, 500)]);
+        assert_eq!(positions(1024, &options(1024, 128)), vec![(0, 1024)]);
+        assert_eq!(layout(1024, 800, &options(1024, 128)), None);
+    }
+
+    #[test]
+    fn layout_is_a_grid() {
+        let tiles = layout(3000, 1000, &options(1024, 128)).unwrap();
+        assert_eq!(tiles.len(), 4);
+        assert!(tiles.iter().all(|tile| tile.height == 1000));
+    }
+
+    #[test]
+    fn rejects_invalid_options() {
+        assert!(options(32, 0).validate().is_err());
+        assert!(options(256, 128).validate().is_err());
+        assert!(TileOptions::default().validate().is_ok());
+    }
+}
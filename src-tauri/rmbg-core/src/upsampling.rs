@@ -0,0 +1,210 @@
+use image::{imageops, DynamicImage, GrayImage};
+use serde::{Deserialize, Serialize};
+
+use crate::blur;
+use crate::resample;
+use crate::{Error, Result};
+
+// Longest side the guided filter solves at, its result is applied at full
+// resolution
+const WORKING_SIZE: u32 = 1024;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum Upsampling {
+    // Plain resampling of the model output, fast but blind to the image
+    #[default]
+    Lanczos3,
+    // Edge-aware upsampling guided by the full resolution image, so mask
+    // edges follow the subject's actual outline
+    GuidedFilter(GuidedFilterOptions),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GuidedFilterOptions {
+    // Window radius in pixels of the image scaled down to at most 1024
+    // pixels, about how far an edge may move to snap to the image
+    pub radius: u32,
+    // Regularization, higher values smooth more and follow weaker image
+    // edges less
+    pub epsilon: f32,
+}
+
+impl Default for GuidedFilterOptions {
+    fn default() -> Self {
+        Self {
+            radius: 8,
+            epsilon: 1e-4,
+        }
+    }
+}
+
+impl Upsampling {
+    pub fn validate(&self) -> Result<()> {
+        if let Upsampling::GuidedFilter(options) = self {
+            if options.radius == 0 {
+                return Err(Error::InvalidOptions(
+                    "Guided filter radius must be at least 1".to_string(),
+                ));
+            }
+            if !(options.epsilon.is_finite() && options.epsilon > 0.0) {
+                return Err(Error::InvalidOptions(
+                    "Guided filter epsilon must be greater than zero".to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // Filter for scaling the model output up to the image size. The guided
+    // filter refines a smooth starting point, Lanczos' ringing would carry
+    // over.
+    pub(crate) fn resize_filter(&self) -> imageops::FilterType {
+        match self {
+            Upsampling::Lanczos3 => imageops::FilterType::Lanczos3,
+            Upsampling::GuidedFilter(_) => imageops::FilterType::Triangle,
+        }
+    }
+}
+
+// Fast guided filter (He & Sun, "Fast Guided Filter") with a color guide:
+// the mask is modeled as a local linear function of the image's RGB values,
+// solved at a reduced resolution and applied at full resolution. Working in
+// color rather than luminance keeps edges between colors of the same
+// brightness, like a red shirt on green grass.
+pub(crate) fn guided_filter(
+    mask: &GrayImage,
+    guide: &DynamicImage,
+    options: &GuidedFilterOptions,
+) -> GrayImage {
+    let (width, height) = mask.dimensions();
+    let factor = (width.max(height) as f32 / WORKING_SIZE as f32).max(1.0);
+    let low_width = ((width as f32 / factor).round() as u32).max(1);
+    let low_height = ((height as f32 / factor).round() as u32).max(1);
+
+    let guide = guide.to_rgb32f();
+    let mask: Vec<f32> = mask.iter().map(|&value| value as f32 / 255.0).collect();
+
+    let (width, height) = (width as usize, height as usize);
+    let (low_width, low_height) = (low_width as usize, low_height as usize);
+    let filter = imageops::FilterType::Triangle;
+    let channel = |index: usize| -> Vec<f32> {
+        let plane: Vec<f32> = guide.pixels().map(|pixel| pixel[index]).collect();
+        resample::resize_plane(&plane, width, height, low_width, low_height, filter)
+    };
+    let (low_r, low_g, low_b) = (channel(0), channel(1), channel(2));
+    let low_mask = resample::resize_plane(&mask, width, height, low_width, low_height, filter);
+
+    let radius = options.radius as usize;
+
+    let products: Vec<[f32; 13]> = (0..low_mask.len())
+        .map(|index| {
+            let (r, g, b, p) = (low_r[index], low_g[index], low_b[index], low_mask[index]);
+            [
+                r,
+                g,
+                b,
+                p,
+                r * r,
+                r * g,
+                r * b,
+                g * g,
+                g * b,
+                b * b,
+                r * p,
+                g * p,
+                b * p,
+            ]
+        })
+        .collect();
+    let means = blur::box_blur(&products, low_width, low_height, radius);
+
+    let coefficients: Vec<[f32; 4]> = means
+        .iter()
+        .map(|m| {
+            let [mean_r, mean_g, mean_b, mean_p] = [m[0], m[1], m[2], m[3]];
+            let epsilon = options.epsilon;
+
+            // Covariance of the guide's channels in the window, regularized
+            let rr = m[4] - mean_r * mean_r + epsilon;
+            let rg = m[5] - mean_r * mean_g;
+            let rb = m[6] - mean_r * mean_b;
+            let gg = m[7] - mean_g * mean_g + epsilon;
+            let gb = m[8] - mean_g * mean_b;
+            let bb = m[9] - mean_b * mean_b + epsilon;
+
+            // Covariance of each channel with the mask
+            let rp = m[10] - mean_r * mean_p;
+            let gp = m[11] - mean_g * mean_p;
+            let bp = m[12] - mean_b * mean_p;
+
+            // Solved with the inverse of the symmetric covariance matrix
+            let c00 = gg * bb - gb * gb;
+            let c01 = gb * rb - rg * bb;
+            let c02 = rg * gb - gg * rb;
+            let c11 = rr * bb - rb * rb;
+            let c12 = rb * rg - rr * gb;
+            let c22 = rr * gg - rg * rg;
+            let det = rr * c00 + rg * c01 + rb * c02;
+
+            let a_r = (c00 * rp + c01 * gp + c02 * bp) / det;
+            let a_g = (c01 * rp + c11 * gp + c12 * bp) / det;
+            let a_b = (c02 * rp + c12 * gp + c22 * bp) / det;
+            [
+                a_r,
+                a_g,
+                a_b,
+                mean_p - a_r * mean_r - a_g * mean_g - a_b * mean_b,
+            ]
+        })
+        .collect();
+    let coefficients = blur::box_blur(&coefficients, low_width, low_height, radius);
+
+    let plane = |index: usize| {
+        let low: Vec<f32> = coefficients.iter().map(|c| c[index]).collect();
+        resample::resize_plane(&low, low_width, low_height, width, height, filter)
+    };
+    let (a_r, a_g, a_b, b) = (plane(0), plane(1), plane(2), plane(3));
+
+    let pixels = guide
+        .pixels()
+        .enumerate()
+        .map(|(index, pixel)| {
+            let value =
+                a_r[index] * pixel[0] + a_g[index] * pixel[1] + a_b[index] * pixel[2] + b[index];
+            (value.clamp(0.0, 1.0) * 255.0).round() as u8
+        })
+        .collect();
+    GrayImage::from_raw(width as u32, height as u32, pixels).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Luma, Rgb, RgbImage};
+
+    #[test]
+    fn follows_edges_between_colors_of_the_same_brightness() {
+        // Red next to a green of about the same luminance
+        let guide = RgbImage::from_fn(64, 32, |x, _| {
+            if x < 32 {
+                Rgb([255, 0, 0])
+            } else {
+                Rgb([0, 76, 0])
+            }
+        });
+        // A soft edge 16 pixels wide centered on the color edge
+        let mask = GrayImage::from_fn(64, 32, |x, _| {
+            let t = ((x as f32 - 24.0) / 16.0).clamp(0.0, 1.0);
+            Luma([((1.0 - t) * 255.0).round() as u8])
+        });
+
+        let options = GuidedFilterOptions::default();
+        let filtered = guided_filter(&mask, &DynamicImage::ImageRgb8(guide), &options);
+        // The soft edge steepens into a step at the color edge
+        let (inside, outside) = (filtered.get_pixel(31, 16)[0], filtered.get_pixel(32, 16)[0]);
+        assert!(inside > mask.get_pixel(31, 16)[0] && outside < mask.get_pixel(32, 16)[0]);
+        assert!(inside - outside > 100);
+    }
+}
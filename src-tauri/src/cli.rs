@@ -0,0 +1,618 @@
+use anyhow::Result;
+use rmbg_core::background::{self, Background};
+use rmbg_core::benchmark::{self, BenchmarkOptions};
+use rmbg_core::canvas::{self, Alignment};
+use rmbg_core::crop::Padding;
+use rmbg_core::effects::Effect;
+use rmbg_core::ensemble::{self, Ensemble, EnsembleMember, EnsembleOptions, Fusion};
+use rmbg_core::evaluation::{self, EvaluationOptions};
+use rmbg_core::mask::MaskOperation;
+use rmbg_core::models::Precision;
+use rmbg_core::portrait::{BlurKind, BlurOptions};
+use rmbg_core::processor::{OutputMode, ProcessOptions};
+use rmbg_core::session::{self, Provider};
+use rmbg_core::upsampling::Upsampling;
+use rmbg_core::{dataset, models, processor, routing};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+use crate::server;
+
+const USAGE: &str = "Usage:
+  rmbg                          Launch the desktop app
+  rmbg remove [options] [<input>] [<output>]
+                                Remove the background of a single image,
+                                use `-` (the default) for stdin/stdout
+  rmbg serve [--host <host>] [--port <port>]
+                                Serve rembg's /api/remove endpoint over HTTP
+  rmbg providers                List the execution providers this build of
+                                ONNX Runtime supports
+  rmbg benchmark [options] <images or folders>...
+                                Compare the speed, memory use and accuracy
+                                of models on your own images
+  rmbg evaluate -m <id> --ground-truth <folder> [options] <images or folders>...
+                                Score a model's masks against ground truth
+                                masks, as CSV or JSON
+
+Remove options:
+  -m, --model <id>              Model to use, defaults to the default model.
+                                `auto` picks the downloaded model best suited
+                                to the image.
+  --ensemble <id[:weight],...>  Fuse the masks of several models, e.g.
+                                `birefnet-general,isnet-general-use:0.5`
+  --fusion <method>             How --ensemble combines masks: mean (the
+                                default), max, median or confidence
+  --precision <fp32|fp16|int8>  Model variant to use, for models that have
+                                lower precision versions
+  --provider <id>               Execution provider to try, may be repeated in
+                                order of preference. Falls back to the CPU.
+  --threads <n>                 Threads used within each operator
+  --inter-threads <n>           Threads used to run operators in parallel
+  --no-arena                    Release CPU memory after each run rather
+                                than keeping it for reuse
+  --no-memory-pattern           Don't plan allocations from the first run,
+                                for inputs that change shape between runs
+  --letterbox                   Keep the aspect ratio when scaling the image
+                                for the model, for panoramas and tall shots
+  --guided                      Scale the mask up guided by the image's edges
+  --tiled                       Refine the mask with extra passes over tiles,
+                                for very large images
+  --tile-size <px>              Tile size for --tiled, 1024 by default
+  --batch-size <n>              Tiles run through the model at once, for
+                                models that accept batches (4 by default)
+  --strip-metadata              Don't copy color profile, EXIF and DPI
+  --keep-all-exif               Copy the whole EXIF block, including GPS
+                                and maker notes, not only the resolution,
+                                color space, capture date and camera
+  --mask <operations>           Refine the mask before applying it, e.g.
+                                `fill-holes,remove-islands=500,feather=1.5`.
+                                Operations run in order: threshold=<0-255>,
+                                erode=<px>, dilate=<px>, fill-holes,
+                                remove-islands=<min area>,
+                                keep-largest[=<count>], feather=<sigma>
+  --invert                      Keep the background and remove the subject
+  --background-only             Output the full image with the subject made
+                                transparent
+  --decontaminate               Remove background color spill from the
+                                subject's semi-transparent edges
+  --crop                        Crop to the subject
+  --padding <px|percent%>       Margin around the cropped subject
+  --aspect <w:h>                Aspect ratio of the cropped canvas
+  --preset <name>               Fit the subject into a saved canvas preset
+  --canvas <width>x<height>     Fit the subject into a canvas of this size
+  --fill <ratio>                Share of the canvas the subject fills
+  --align <center|bottom>       Where the subject sits on the canvas
+  --background <r,g,b[,a]>      Place the subject on a solid color
+  --background-image <path>     Place the subject on an image
+  --shadow                      Draw a drop shadow on the background
+  --contact-shadow              Draw a soft shadow where the subject stands
+  --reflection                  Draw a mirrored reflection below the subject
+  --blur <radius>               Blur the background instead of removing it
+  --lens                        Use a lens blur rather than a Gaussian one
+  --blur-feather <sigma>        Softness of the subject's edge on the blur
+
+Benchmark options:
+  -m, --model <id>              Model to benchmark, may be repeated. Defaults
+                                to every downloaded model.
+  --precision <fp32|fp16|int8>  Model variant to benchmark
+  --provider <id>               Execution provider to try, may be repeated
+  --ground-truth <folder>       Masks named like the images, to report each
+                                model's IoU
+  --json                        Print the full results, with every image's
+                                timing, as JSON
+
+Evaluate options:
+  -m, --model <id>              Model to evaluate, defaults to the default
+                                model
+  --precision <fp32|fp16|int8>  Model variant to evaluate
+  --provider <id>               Execution provider to try, may be repeated
+  --ground-truth <folder>       Masks named like the images
+  --json                        Print JSON rather than CSV";
+
+// Runs a command line subcommand if one was given. Returns `None` when the
+// arguments don't name a subcommand, in which case the GUI should start.
+pub fn run_cli(args: Vec<String>) -> Option<i32> {
+    let (command, rest) = args.split_first()?;
+
+    let result = match command.as_str() {
+        "remove" => remove(rest),
+        "serve" => serve(rest),
+        "benchmark" => benchmark(rest),
+        "evaluate" => evaluate(rest),
+        "providers" => {
+            for provider in session::available_providers() {
+                println!("{}", provider);
+            }
+            Ok(())
+        }
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        _ => return None,
+    };
+
+    match result {
+        Ok(()) => Some(0),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            Some(1)
+        }
+    }
+}
+
+fn remove(args: &[String]) -> Result<()> {
+    let mut model_id = models::get_default_model().id;
+    let mut precision = Precision::default();
+    let mut options = ProcessOptions::default();
+    let mut ensemble: Option<EnsembleOptions> = None;
+    let mut paths = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--model" | "-m" => model_id = option_value(&mut args, arg)?.to_string(),
+            "--ensemble" => {
+                let members = parse_ensemble(option_value(&mut args, arg)?)?;
+                ensemble.get_or_insert_with(Default::default).models = members;
+            }
+            "--fusion" => {
+                let value = option_value(&mut args, arg)?;
+                ensemble.get_or_insert_with(Default::default).fusion = Fusion::from_id(value)
+                    .ok_or_else(|| anyhow::anyhow!("Invalid fusion method: {}", value))?;
+            }
+            "--precision" => {
+                let value = option_value(&mut args, arg)?;
+                precision = Precision::from_id(value)
+                    .ok_or_else(|| anyhow::anyhow!("Invalid precision: {}", value))?;
+            }
+            "--provider" => {
+                let value = option_value(&mut args, arg)?;
+                let provider = Provider::from_id(value)
+                    .ok_or_else(|| anyhow::anyhow!("Unknown execution provider: {}", value))?;
+                options.session.providers.push(provider);
+            }
+            "--threads" => {
+                let value = option_value(&mut args, arg)?;
+                options.session.intra_threads = Some(
+                    value
+                        .parse()
+                        .map_err(|_| anyhow::anyhow!("Invalid thread count: {}", value))?,
+                );
+            }
+            "--inter-threads" => {
+                let value = option_value(&mut args, arg)?;
+                options.session.inter_threads = Some(
+                    value
+                        .parse()
+                        .map_err(|_| anyhow::anyhow!("Invalid thread count: {}", value))?,
+                );
+            }
+            "--no-arena" => options.session.memory_arena = false,
+            "--no-memory-pattern" => options.session.memory_pattern = false,
+            "--letterbox" => options.inference.letterbox = true,
+            "--guided" => {
+                options.inference.upsampling = Upsampling::GuidedFilter(Default::default())
+            }
+            "--tiled" => {
+                options
+                    .inference
+                    .tiling
+                    .get_or_insert_with(Default::default);
+            }
+            "--tile-size" => {
+                let value = option_value(&mut args, arg)?;
+                options
+                    .inference
+                    .tiling
+                    .get_or_insert_with(Default::default)
+                    .tile_size = value
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid tile size: {}", value))?;
+            }
+            "--batch-size" => {
+                let value = option_value(&mut args, arg)?;
+                options.inference.batch_size = value
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid batch size: {}", value))?;
+            }
+            "--strip-metadata" => options.strip_metadata = true,
+            "--keep-all-exif" => options.keep_all_exif = true,
+            "--mask" => {
+                let operations = parse_mask_operations(option_value(&mut args, arg)?)?;
+                options.mask_operations.extend(operations);
+            }
+            "--invert" => options.invert_mask = true,
+            "--background-only" => options.output = OutputMode::BackgroundOnly,
+            "--decontaminate" => options.decontaminate_colors = true,
+            "--crop" => {
+                options.crop.get_or_insert_with(Default::default);
+            }
+            "--padding" => {
+                let padding = parse_padding(option_value(&mut args, arg)?)?;
+                options.crop.get_or_insert_with(Default::default).padding = padding;
+            }
+            "--aspect" => {
+                let aspect_ratio = parse_aspect_ratio(option_value(&mut args, arg)?)?;
+                options
+                    .crop
+                    .get_or_insert_with(Default::default)
+                    .aspect_ratio = Some(aspect_ratio);
+            }
+            "--preset" => {
+                options.canvas = Some(canvas::get_preset(option_value(&mut args, arg)?)?.options)
+            }
+            "--canvas" => {
+                let (width, height) = parse_size(option_value(&mut args, arg)?)?;
+                let canvas = options.canvas.get_or_insert_with(Default::default);
+                canvas.width = width;
+                canvas.height = height;
+            }
+            "--fill" => {
+                let value = option_value(&mut args, arg)?;
+                options
+                    .canvas
+                    .get_or_insert_with(Default::default)
+                    .fill_ratio = value
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid fill ratio: {}", value))?;
+            }
+            "--align" => {
+                let alignment = match option_value(&mut args, arg)? {
+                    "center" => Alignment::Center,
+                    "bottom" => Alignment::Bottom,
+                    value => return Err(anyhow::anyhow!("Invalid alignment: {}", value)),
+                };
+                options
+                    .canvas
+                    .get_or_insert_with(Default::default)
+                    .alignment = alignment;
+            }
+            "--background" => {
+                let value = option_value(&mut args, arg)?;
+                let color = background::parse_color(value)
+                    .ok_or_else(|| anyhow::anyhow!("Invalid color: {}", value))?;
+                options.background = Some(Background::Color(color.0));
+            }
+            "--background-image" => {
+                let path = option_value(&mut args, arg)?;
+                options.background = Some(Background::Image(path.into()));
+            }
+            "--shadow" => options.effects.push(Effect::DropShadow(Default::default())),
+            "--contact-shadow" => options
+                .effects
+                .push(Effect::ContactShadow(Default::default())),
+            "--reflection" => options.effects.push(Effect::Reflection(Default::default())),
+            "--blur" => {
+                let value = option_value(&mut args, arg)?;
+                blur_options(&mut options).radius = value
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid blur radius: {}", value))?;
+            }
+            "--lens" => blur_options(&mut options).kind = BlurKind::Lens,
+            "--blur-feather" => {
+                let value = option_value(&mut args, arg)?;
+                blur_options(&mut options).feather = value
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid blur feather: {}", value))?;
+            }
+            "-" => paths.push(arg.as_str()),
+            _ if arg.starts_with('-') => {
+                return Err(anyhow::anyhow!("Unknown option: {}\n\n{}", arg, USAGE))
+            }
+            _ => paths.push(arg.as_str()),
+        }
+    }
+
+    let (input, output) = match paths.as_slice() {
+        [] => ("-", "-"),
+        [input] => (*input, "-"),
+        [input, output] => (*input, *output),
+        _ => return Err(anyhow::anyhow!("Too many arguments\n\n{}", USAGE)),
+    };
+
+    let input_bytes = if input == "-" {
+        let mut bytes = Vec::new();
+        std::io::stdin().lock().read_to_end(&mut bytes)?;
+        bytes
+    } else {
+        std::fs::read(input)?
+    };
+
+    let output_bytes = if let Some(ensemble) = ensemble {
+        let mut ensemble = Ensemble::new(ensemble, precision, &options.session)?;
+        ensemble::process_image_bytes(&mut ensemble, &input_bytes, &options)?
+    } else if model_id == routing::AUTO_MODEL_ID {
+        let mut auto = routing::AutoRemover::new(precision, &options.session)?;
+        let (output_bytes, route) =
+            routing::process_image_bytes(&mut auto, &input_bytes, &options)?;
+        eprintln!("Using {} for a {}", route.model_id, route.kind);
+        output_bytes
+    } else {
+        let model_path = models::get_variant_path(&model_id, precision)?;
+        processor::process_image_bytes(&model_path, &input_bytes, &options)?
+    };
+
+    if output == "-" {
+        let mut stdout = std::io::stdout().lock();
+        stdout.write_all(&output_bytes)?;
+        stdout.flush()?;
+    } else {
+        std::fs::write(output, output_bytes)?;
+    }
+
+    Ok(())
+}
+
+fn serve(args: &[String]) -> Result<()> {
+    let mut host = server::DEFAULT_HOST.to_string();
+    let mut port = server::DEFAULT_PORT;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--host" => host = option_value(&mut args, arg)?.to_string(),
+            "--port" | "-p" => {
+                port = option_value(&mut args, arg)?
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid port"))?
+            }
+            _ => return Err(anyhow::anyhow!("Unknown option: {}\n\n{}", arg, USAGE)),
+        }
+    }
+
+    let runtime = tokio::runtime::Runtime::new()?;
+    runtime.block_on(server::serve(&host, port))
+}
+
+fn benchmark(args: &[String]) -> Result<()> {
+    let mut model_ids = Vec::new();
+    let mut options = BenchmarkOptions::default();
+    let mut json = false;
+    let mut paths = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--model" | "-m" => model_ids.push(option_value(&mut args, arg)?.to_string()),
+            "--precision" => {
+                let value = option_value(&mut args, arg)?;
+                options.precision = Precision::from_id(value)
+                    .ok_or_else(|| anyhow::anyhow!("Invalid precision: {}", value))?;
+            }
+            "--provider" => {
+                let value = option_value(&mut args, arg)?;
+                let provider = Provider::from_id(value)
+                    .ok_or_else(|| anyhow::anyhow!("Unknown execution provider: {}", value))?;
+                options.session.providers.push(provider);
+            }
+            "--ground-truth" => {
+                options.ground_truth_dir = Some(option_value(&mut args, arg)?.into());
+            }
+            "--json" => json = true,
+            _ if arg.starts_with('-') => {
+                return Err(anyhow::anyhow!("Unknown option: {}\n\n{}", arg, USAGE))
+            }
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    let images = dataset::collect_images(&paths)?;
+    if images.is_empty() {
+        return Err(anyhow::anyhow!("No images to benchmark\n\n{}", USAGE));
+    }
+    if model_ids.is_empty() {
+        model_ids = models::downloaded_models(options.precision)?;
+    }
+
+    // Keep going when a model fails, the others are still worth comparing
+    let mut results = Vec::new();
+    for model_id in &model_ids {
+        eprintln!("Benchmarking {}...", model_id);
+        match benchmark::benchmark_model(model_id, &images, &options) {
+            Ok(result) => results.push(result),
+            Err(e) => eprintln!("Error: {}: {}", model_id, e),
+        }
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&results)?);
+        return Ok(());
+    }
+
+    println!(
+        "{:<24} {:<9} {:>9} {:>9} {:>9} {:>9} {:>9} {:>6}",
+        "Model", "Provider", "Load ms", "Warmup ms", "Mean ms", "Median ms", "Peak MB", "IoU"
+    );
+    for result in &results {
+        let optional = |value: Option<f64>, precision: usize| {
+            value.map_or("-".to_string(), |value| format!("{:.*}", precision, value))
+        };
+        println!(
+            "{:<24} {:<9} {:>9.0} {:>9.0} {:>9.1} {:>9.1} {:>9} {:>6}",
+            result.model_id,
+            result.provider,
+            result.load_time_ms,
+            result.warmup_ms,
+            result.mean_latency_ms,
+            result.median_latency_ms,
+            optional(result.peak_memory_mb, 0),
+            optional(result.mean_iou, 3),
+        );
+    }
+
+    Ok(())
+}
+
+fn evaluate(args: &[String]) -> Result<()> {
+    let mut model_id = models::get_default_model().id;
+    let mut options = EvaluationOptions::default();
+    let mut ground_truth_dir = None;
+    let mut json = false;
+    let mut paths = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--model" | "-m" => model_id = option_value(&mut args, arg)?.to_string(),
+            "--precision" => {
+                let value = option_value(&mut args, arg)?;
+                options.precision = Precision::from_id(value)
+                    .ok_or_else(|| anyhow::anyhow!("Invalid precision: {}", value))?;
+            }
+            "--provider" => {
+                let value = option_value(&mut args, arg)?;
+                let provider = Provider::from_id(value)
+                    .ok_or_else(|| anyhow::anyhow!("Unknown execution provider: {}", value))?;
+                options.session.providers.push(provider);
+            }
+            "--ground-truth" => {
+                ground_truth_dir = Some(PathBuf::from(option_value(&mut args, arg)?));
+            }
+            "--json" => json = true,
+            _ if arg.starts_with('-') => {
+                return Err(anyhow::anyhow!("Unknown option: {}\n\n{}", arg, USAGE))
+            }
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    let ground_truth_dir = ground_truth_dir
+        .ok_or_else(|| anyhow::anyhow!("Missing --ground-truth folder\n\n{}", USAGE))?;
+    let images = dataset::collect_images(&paths)?;
+    if images.is_empty() {
+        return Err(anyhow::anyhow!("No images to evaluate\n\n{}", USAGE));
+    }
+
+    let evaluation = evaluation::evaluate_model(&model_id, &images, &ground_truth_dir, &options)?;
+    for path in &evaluation.skipped {
+        eprintln!("No ground truth for {}, skipped", path.display());
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&evaluation)?);
+    } else {
+        print!("{}", evaluation.to_csv());
+    }
+
+    Ok(())
+}
+
+// Switches to blurred background output, keeping blur settings given so far
+fn blur_options(options: &mut ProcessOptions) -> &mut BlurOptions {
+    if !matches!(options.output, OutputMode::BlurredBackground(_)) {
+        options.output = OutputMode::BlurredBackground(BlurOptions::default());
+    }
+    match &mut options.output {
+        OutputMode::BlurredBackground(blur) => blur,
+        _ => unreachable!(),
+    }
+}
+
+fn option_value<'a>(args: &mut impl Iterator<Item = &'a String>, option: &str) -> Result<&'a str> {
+    args.next()
+        .map(|value| value.as_str())
+        .ok_or_else(|| anyhow::anyhow!("Missing value for {}", option))
+}
+
+// Accepts `20` for pixels or `5%` for a percentage of the subject size
+fn parse_padding(value: &str) -> Result<Padding> {
+    let invalid = || anyhow::anyhow!("Invalid padding: {}", value);
+
+    match value.strip_suffix('%') {
+        Some(percent) => Ok(Padding::Percent(percent.parse().map_err(|_| invalid())?)),
+        None => Ok(Padding::Pixels(value.parse().map_err(|_| invalid())?)),
+    }
+}
+
+// Comma separated `name[=value]` operations, applied in the order given
+fn parse_mask_operations(value: &str) -> Result<Vec<MaskOperation>> {
+    value
+        .split(',')
+        .map(|operation| {
+            let operation = operation.trim();
+            let invalid = || anyhow::anyhow!("Invalid mask operation: {}", operation);
+            let (name, argument) = match operation.split_once('=') {
+                Some((name, argument)) => (name, Some(argument)),
+                None => (operation, None),
+            };
+            let value = || argument.ok_or_else(invalid);
+
+            Ok(match name {
+                "threshold" => MaskOperation::Threshold {
+                    value: value()?.parse().map_err(|_| invalid())?,
+                },
+                "erode" => MaskOperation::Erode {
+                    radius: value()?.parse().map_err(|_| invalid())?,
+                },
+                "dilate" => MaskOperation::Dilate {
+                    radius: value()?.parse().map_err(|_| invalid())?,
+                },
+                "fill-holes" if argument.is_none() => MaskOperation::FillHoles,
+                "remove-islands" => MaskOperation::RemoveIslands {
+                    min_area: value()?.parse().map_err(|_| invalid())?,
+                },
+                "keep-largest" => MaskOperation::KeepLargest {
+                    count: argument.map_or(Ok(1), str::parse).map_err(|_| invalid())?,
+                },
+                "feather" => MaskOperation::Feather {
+                    sigma: value()?.parse().map_err(|_| invalid())?,
+                },
+                _ => return Err(invalid()),
+            })
+        })
+        .collect()
+}
+
+// Comma separated `model[:weight]` entries, weighing 1 by default
+fn parse_ensemble(value: &str) -> Result<Vec<EnsembleMember>> {
+    value
+        .split(',')
+        .map(|member| {
+            let member = member.trim();
+            let (model_id, weight) = match member.split_once(':') {
+                Some((model_id, weight)) => (
+                    model_id,
+                    weight
+                        .parse()
+                        .map_err(|_| anyhow::anyhow!("Invalid ensemble model: {}", member))?,
+                ),
+                None => (member, 1.0),
+            };
+            Ok(EnsembleMember {
+                model_id: model_id.to_string(),
+                weight,
+            })
+        })
+        .collect()
+}
+
+fn parse_size(value: &str) -> Result<(u32, u32)> {
+    let invalid = || anyhow::anyhow!("Invalid size: {}", value);
+
+    let (width, height) = value.split_once(['x', 'X']).ok_or_else(invalid)?;
+    Ok((
+        width.parse().map_err(|_| invalid())?,
+        height.parse().map_err(|_| invalid())?,
+    ))
+}
+
+// Accepts `4:3` or a plain ratio like `1.5`
+fn parse_aspect_ratio(value: &str) -> Result<f32> {
+    let invalid = || anyhow::anyhow!("Invalid aspect ratio: {}", value);
+
+    let ratio = match value.split_once(':') {
+        Some((width, height)) => {
+            let width: f32 = width.parse().map_err(|_| invalid())?;
+            let height: f32 = height.parse().map_err(|_| invalid())?;
+            width / height
+        }
+        None => value.parse().map_err(|_| invalid())?,
+    };
+
+    if ratio.is_finite() && ratio > 0.0 {
+        Ok(ratio)
+    } else {
+        Err(invalid())
+    }
+}
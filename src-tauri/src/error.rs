@@ -0,0 +1,141 @@
+use image::error::{ImageError, LimitErrorKind};
+use serde::{Deserialize, Serialize};
+use std::io;
+
+// What went wrong, tagged with a stable `code` the frontend can match on.
+// Variant fields carry whatever context is useful to show or act on.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub enum ErrorKind {
+    ModelNotFound { model_id: String },
+    ModelNotDownloaded { model_id: String, path: String },
+    InvalidModel { model_id: Option<String> },
+    UnsupportedImageFormat,
+    InvalidImage,
+    OutOfMemory,
+    FileNotFound { path: Option<String> },
+    PermissionDenied { path: Option<String> },
+    InvalidPath { path: String },
+    InvalidOptions,
+    PresetNotFound { name: String },
+    InvalidPresetFile { path: String },
+    DownloadFailed { status: Option<u16> },
+    Inference,
+    Io { path: Option<String> },
+    Internal,
+}
+
+// Error returned from every command and per-image result. Serializes as the
+// flattened kind plus a human readable `message`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommandError {
+    #[serde(flatten)]
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+impl CommandError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+        }
+    }
+
+    pub fn invalid_path(path: &str, message: impl Into<String>) -> Self {
+        Self::new(
+            ErrorKind::InvalidPath {
+                path: path.to_string(),
+            },
+            message,
+        )
+    }
+}
+
+impl std::fmt::Display for CommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+impl From<rmbg_core::Error> for CommandError {
+    fn from(error: rmbg_core::Error) -> Self {
+        use rmbg_core::Error;
+
+        let message = error.to_string();
+        let kind = match error {
+            Error::ModelNotFound(model_id) => ErrorKind::ModelNotFound { model_id },
+            Error::VariantNotFound { model_id, .. } => ErrorKind::ModelNotFound { model_id },
+            Error::ModelNotDownloaded { model_id, path } => ErrorKind::ModelNotDownloaded {
+                model_id,
+                path: path.to_string_lossy().to_string(),
+            },
+            Error::ModelHasNoFiles(model_id) => ErrorKind::InvalidModel {
+                model_id: Some(model_id),
+            },
+            Error::InvalidModelInput(_) => ErrorKind::Inference,
+            Error::InvalidModelOutput(_) => ErrorKind::InvalidModel { model_id: None },
+            Error::InvalidOptions(_) => ErrorKind::InvalidOptions,
+            Error::GroundTruthSizeMismatch { .. } => ErrorKind::InvalidImage,
+            Error::PresetNotFound(name) => ErrorKind::PresetNotFound { name },
+            Error::InvalidPresetFile { path, .. } => ErrorKind::InvalidPresetFile {
+                path: path.to_string_lossy().to_string(),
+            },
+            Error::HomeDirNotFound => ErrorKind::Internal,
+            Error::DownloadFailed(status) => ErrorKind::DownloadFailed {
+                status: Some(status.as_u16()),
+            },
+            Error::Http(e) => ErrorKind::DownloadFailed {
+                status: e.status().map(|s| s.as_u16()),
+            },
+            Error::File { path, source } => {
+                io_error_kind(&source, Some(path.to_string_lossy().to_string()))
+            }
+            Error::Io(e) => io_error_kind(&e, None),
+            Error::Image(e) => image_error_kind(&e),
+            Error::Ort(e) => ort_error_kind(&e),
+        };
+
+        Self::new(kind, message)
+    }
+}
+
+fn io_error_kind(error: &io::Error, path: Option<String>) -> ErrorKind {
+    match error.kind() {
+        io::ErrorKind::NotFound => ErrorKind::FileNotFound { path },
+        io::ErrorKind::PermissionDenied => ErrorKind::PermissionDenied { path },
+        io::ErrorKind::OutOfMemory => ErrorKind::OutOfMemory,
+        _ => ErrorKind::Io { path },
+    }
+}
+
+fn image_error_kind(error: &ImageError) -> ErrorKind {
+    match error {
+        ImageError::Unsupported(_) => ErrorKind::UnsupportedImageFormat,
+        ImageError::Decoding(_) | ImageError::Parameter(_) => ErrorKind::InvalidImage,
+        ImageError::Limits(e) => match e.kind() {
+            LimitErrorKind::InsufficientMemory => ErrorKind::OutOfMemory,
+            _ => ErrorKind::InvalidImage,
+        },
+        ImageError::IoError(e) => io_error_kind(e, None),
+        ImageError::Encoding(_) => ErrorKind::Internal,
+    }
+}
+
+fn ort_error_kind(error: &ort::Error) -> ErrorKind {
+    // ONNX Runtime reports failed allocations as generic runtime exceptions
+    let message = error.message().to_lowercase();
+    if message.contains("bad_alloc") || message.contains("failed to allocate") {
+        return ErrorKind::OutOfMemory;
+    }
+
+    match error.code() {
+        ort::ErrorCode::NoSuchFile => ErrorKind::FileNotFound { path: None },
+        ort::ErrorCode::NoModel
+        | ort::ErrorCode::InvalidProtobuf
+        | ort::ErrorCode::InvalidGraph => ErrorKind::InvalidModel { model_id: None },
+        _ => ErrorKind::Inference,
+    }
+}
@@ -1,9 +1,16 @@
-mod downloader;
-mod models;
-mod processor;
+mod cli;
+mod error;
+mod server;
 
+pub use cli::run_cli;
+
+use error::CommandError;
+use rmbg_core::{
+    benchmark, canvas, dataset, downloader, ensemble, evaluation, models, processor, routing,
+    session,
+};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use tauri::{Emitter, Window};
 
 #[derive(Clone, Serialize)]
@@ -22,6 +29,13 @@ struct ProcessingProgressPayload {
     file_name: String,
 }
 
+#[derive(Clone, Serialize)]
+struct BenchmarkProgressPayload {
+    current: usize,
+    total: usize,
+    model_id: String,
+}
+
 // Model management commands
 
 #[tauri::command]
@@ -35,64 +49,49 @@ fn get_default_model() -> models::ModelInfo {
 }
 
 #[tauri::command]
-fn get_model_status(model_id: String) -> Result<models::ModelStatus, String> {
-    models::get_model_status(&model_id).map_err(|e| e.to_string())
+fn get_model_status(model_id: String) -> Result<models::ModelStatus, CommandError> {
+    models::get_model_status(&model_id).map_err(CommandError::from)
 }
 
 #[tauri::command]
-fn is_model_downloaded(model_id: String) -> Result<bool, String> {
-    models::is_model_downloaded(&model_id).map_err(|e| e.to_string())
+fn is_model_downloaded(model_id: String) -> Result<bool, CommandError> {
+    models::is_model_downloaded(&model_id).map_err(CommandError::from)
 }
 
 #[tauri::command]
-fn get_models_dir() -> Result<String, String> {
+fn get_models_dir() -> Result<String, CommandError> {
     models::get_models_dir()
         .map(|p| p.to_string_lossy().to_string())
-        .map_err(|e| e.to_string())
+        .map_err(CommandError::from)
 }
 
 #[tauri::command]
-async fn download_model(model_id: String, window: Window) -> Result<(), String> {
-    let model = models::get_model_by_id(&model_id)
-        .ok_or_else(|| format!("Model not found: {}", model_id))?;
-
-    let models_dir = models::get_models_dir().map_err(|e| e.to_string())?;
-
-    for file in &model.files {
-        let dest_path = models_dir.join(&file.name);
-
-        // Skip if file already exists
-        if dest_path.exists() {
-            continue;
-        }
-
-        let model_id_clone = model_id.clone();
-        let file_name = file.name.clone();
-        let window_clone = window.clone();
-
-        downloader::download_file(&file.url, &dest_path, move |progress| {
-            let percentage = if progress.total > 0 {
-                (progress.downloaded as f64 / progress.total as f64) * 100.0
-            } else {
-                0.0
-            };
-
-            let _ = window_clone.emit(
-                "download-progress",
-                DownloadProgressPayload {
-                    model_id: model_id_clone.clone(),
-                    file_name: file_name.clone(),
-                    downloaded: progress.downloaded,
-                    total: progress.total,
-                    percentage,
-                },
-            );
-        })
-        .await
-        .map_err(|e| e.to_string())?;
-    }
+async fn download_model(
+    model_id: String,
+    precision: Option<models::Precision>,
+    window: Window,
+) -> Result<(), CommandError> {
+    let precision = precision.unwrap_or_default();
+    downloader::download_variant(&model_id, precision, |file, progress| {
+        let percentage = if progress.total > 0 {
+            (progress.downloaded as f64 / progress.total as f64) * 100.0
+        } else {
+            0.0
+        };
 
-    Ok(())
+        let _ = window.emit(
+            "download-progress",
+            DownloadProgressPayload {
+                model_id: model_id.clone(),
+                file_name: file.name.clone(),
+                downloaded: progress.downloaded,
+                total: progress.total,
+                percentage,
+            },
+        );
+    })
+    .await
+    .map_err(CommandError::from)
 }
 
 // Image processing commands
@@ -101,7 +100,16 @@ async fn download_model(model_id: String, window: Window) -> Result<(), String>
 struct ProcessImageRequest {
     image_paths: Vec<String>,
     model_id: String,
+    // Which of the model's variants to run, full precision by default
+    #[serde(default)]
+    precision: models::Precision,
     output_dir: Option<String>,
+    #[serde(default)]
+    options: processor::ProcessOptions,
+    // Models whose masks are fused for each image, used instead of
+    // `model_id` when set
+    #[serde(default)]
+    ensemble: Option<ensemble::EnsembleOptions>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -109,96 +117,355 @@ struct ProcessImageResult {
     input_path: String,
     output_path: String,
     success: bool,
-    error: Option<String>,
+    error: Option<CommandError>,
+    // Model the image went through, the one picked for it with "auto".
+    // `None` for ensembles and failed images.
+    model_id: Option<String>,
+    // What "auto" took the image for
+    image_kind: Option<routing::ImageKind>,
+    // Primary execution provider of the model. Nodes it doesn't support
+    // still run on the CPU.
+    execution_provider: Option<session::Provider>,
+}
+
+impl ProcessImageResult {
+    fn new(input_path: &str, output_path: &Path, outcome: Result<Processed, CommandError>) -> Self {
+        let (processed, error) = match outcome {
+            Ok(processed) => (Some(processed), None),
+            Err(e) => (None, Some(e)),
+        };
+        let success = processed.is_some();
+        let processed = processed.unwrap_or_default();
+        Self {
+            input_path: input_path.to_string(),
+            output_path: if success {
+                output_path.to_string_lossy().to_string()
+            } else {
+                String::new()
+            },
+            success,
+            error,
+            model_id: processed.model_id,
+            image_kind: processed.image_kind,
+            execution_provider: processed.execution_provider,
+        }
+    }
+}
+
+// How an image was processed, for its result
+#[derive(Default)]
+struct Processed {
+    model_id: Option<String>,
+    image_kind: Option<routing::ImageKind>,
+    execution_provider: Option<session::Provider>,
+}
+
+// Which model, or models, each image of a request goes through
+enum Removers {
+    // The requested model for every image, in batches where it accepts them
+    Single {
+        model_id: String,
+        remover: processor::BackgroundRemover,
+    },
+    // The downloaded model best suited to each image
+    Auto(routing::AutoRemover),
+    // The fused mask of several models
+    Ensemble(ensemble::Ensemble),
+}
+
+impl Removers {
+    // Loads the models up front where they're known, so a missing one fails
+    // the request before any image is processed
+    fn new(request: &ProcessImageRequest) -> Result<Self, CommandError> {
+        let session = &request.options.session;
+        if let Some(options) = &request.ensemble {
+            let ensemble = ensemble::Ensemble::new(options.clone(), request.precision, session)?;
+            return Ok(Self::Ensemble(ensemble));
+        }
+        if request.model_id == routing::AUTO_MODEL_ID {
+            return Ok(Self::Auto(routing::AutoRemover::new(
+                request.precision,
+                session,
+            )?));
+        }
+
+        let model_path = models::get_variant_path(&request.model_id, request.precision)?;
+        Ok(Self::Single {
+            model_id: request.model_id.clone(),
+            remover: processor::BackgroundRemover::with_options(&model_path, session)?,
+        })
+    }
+
+    fn batch_size(&self, options: &processor::InferenceOptions) -> usize {
+        match self {
+            Self::Single { remover, .. } => remover.batch_size(options),
+            Self::Auto(_) | Self::Ensemble(_) => 1,
+        }
+    }
+
+    // Processes a batch of input and output paths. A failed inference run
+    // fails all the images in its batch.
+    fn process(
+        &mut self,
+        paths: &[(&Path, &Path)],
+        options: &processor::ProcessOptions,
+    ) -> Vec<Result<Processed, CommandError>> {
+        match self {
+            Self::Single { model_id, remover } => {
+                let execution_provider = remover.provider();
+                match processor::process_image_batch_with(remover, paths, options) {
+                    Ok(outcomes) => outcomes
+                        .into_iter()
+                        .map(|outcome| -> Result<Processed, CommandError> {
+                            outcome?;
+                            Ok(Processed {
+                                model_id: Some(model_id.clone()),
+                                image_kind: None,
+                                execution_provider: Some(execution_provider),
+                            })
+                        })
+                        .collect(),
+                    Err(e) => vec![Err(CommandError::from(e)); paths.len()],
+                }
+            }
+            Self::Auto(auto) => paths
+                .iter()
+                .map(
+                    |(input_path, output_path)| -> Result<Processed, CommandError> {
+                        let route = routing::process_image(auto, input_path, output_path, options)?;
+                        Ok(Processed {
+                            model_id: Some(route.model_id),
+                            image_kind: Some(route.kind),
+                            execution_provider: Some(route.provider),
+                        })
+                    },
+                )
+                .collect(),
+            Self::Ensemble(ensemble) => {
+                // Looked up first, so a failure can't mark saved images as failed
+                let execution_provider = match ensemble.provider() {
+                    Ok(provider) => provider,
+                    Err(e) => return vec![Err(CommandError::from(e)); paths.len()],
+                };
+                paths
+                    .iter()
+                    .map(
+                        |(input_path, output_path)| -> Result<Processed, CommandError> {
+                            ensemble::process_image(ensemble, input_path, output_path, options)?;
+                            Ok(Processed {
+                                execution_provider: Some(execution_provider),
+                                ..Processed::default()
+                            })
+                        },
+                    )
+                    .collect()
+            }
+        }
+    }
 }
 
 #[tauri::command]
 async fn process_images(
     request: ProcessImageRequest,
     window: Window,
-) -> Result<Vec<ProcessImageResult>, String> {
-    let model = models::get_model_by_id(&request.model_id)
-        .ok_or_else(|| format!("Model not found: {}", request.model_id))?;
-
-    let models_dir = models::get_models_dir().map_err(|e| e.to_string())?;
-
-    // Get the model file path (use first file for single-file models)
-    let model_file = model
-        .files
-        .first()
-        .ok_or_else(|| "Model has no files".to_string())?;
-    let model_path = models_dir.join(&model_file.name);
-
-    if !model_path.exists() {
-        return Err(format!(
-            "Model file not found. Please download the model first."
-        ));
-    }
+) -> Result<Vec<ProcessImageResult>, CommandError> {
+    request.options.validate()?;
+
+    // Load the models once for the whole batch
+    let mut removers = Removers::new(&request)?;
 
     let mut results = Vec::new();
     let total = request.image_paths.len();
 
-    for (index, input_path) in request.image_paths.iter().enumerate() {
-        let input_path_buf = PathBuf::from(input_path);
+    // Images go through the model in batches when it accepts them
+    let batch_size = removers.batch_size(&request.options.inference);
+    for batch in request.image_paths.chunks(batch_size) {
+        let mut paths = Vec::with_capacity(batch.len());
+        for input_path in batch {
+            let output_path = output_path_for(input_path, request.output_dir.as_deref())?;
+            paths.push((PathBuf::from(input_path), output_path));
+        }
+
+        // Report the batch's first image as it starts, so progress moves
+        // while the batch runs rather than after it
+        emit_processing_progress(&window, results.len() + 1, total, &paths[0].0);
+
+        let path_refs: Vec<_> = paths
+            .iter()
+            .map(|(input, output)| (input.as_path(), output.as_path()))
+            .collect();
+        let outcomes = removers.process(&path_refs, &request.options);
 
-        // Emit progress
+        for ((input_path, (_, output_path)), outcome) in batch.iter().zip(&paths).zip(outcomes) {
+            results.push(ProcessImageResult::new(input_path, output_path, outcome));
+        }
+    }
+
+    Ok(results)
+}
+
+// `<stem>_no_bg.png` in the output folder, or next to the input without one
+fn output_path_for(input_path: &str, output_dir: Option<&str>) -> Result<PathBuf, CommandError> {
+    let input_path_buf = PathBuf::from(input_path);
+    let file_stem = input_path_buf
+        .file_stem()
+        .ok_or_else(|| CommandError::invalid_path(input_path, "Invalid input file name"))?;
+    let file_name = format!("{}_no_bg.png", file_stem.to_string_lossy());
+
+    if let Some(output_dir) = output_dir {
+        Ok(PathBuf::from(output_dir).join(file_name))
+    } else {
+        let parent = input_path_buf
+            .parent()
+            .ok_or_else(|| CommandError::invalid_path(input_path, "Invalid input file path"))?;
+        Ok(parent.join(file_name))
+    }
+}
+
+fn emit_processing_progress(window: &Window, current: usize, total: usize, input_path: &Path) {
+    let _ = window.emit(
+        "processing-progress",
+        ProcessingProgressPayload {
+            current,
+            total,
+            file_name: input_path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string(),
+        },
+    );
+}
+
+// Benchmark commands
+
+#[derive(Debug, Serialize, Deserialize)]
+struct BenchmarkRequest {
+    // Models to compare, every downloaded model if empty
+    #[serde(default)]
+    model_ids: Vec<String>,
+    // Images or folders of images to run the models on
+    image_paths: Vec<String>,
+    #[serde(default)]
+    options: benchmark::BenchmarkOptions,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ModelBenchmarkResult {
+    model_id: String,
+    benchmark: Option<benchmark::ModelBenchmark>,
+    error: Option<CommandError>,
+}
+
+#[tauri::command]
+async fn benchmark_models(
+    request: BenchmarkRequest,
+    window: Window,
+) -> Result<Vec<ModelBenchmarkResult>, CommandError> {
+    request.options.validate()?;
+    let paths: Vec<PathBuf> = request.image_paths.iter().map(PathBuf::from).collect();
+    let images = dataset::collect_images(&paths)?;
+    let model_ids = if request.model_ids.is_empty() {
+        models::downloaded_models(request.options.precision)?
+    } else {
+        request.model_ids
+    };
+
+    let mut results = Vec::new();
+    let total = model_ids.len();
+
+    for (index, model_id) in model_ids.into_iter().enumerate() {
         let _ = window.emit(
-            "processing-progress",
-            ProcessingProgressPayload {
+            "benchmark-progress",
+            BenchmarkProgressPayload {
                 current: index + 1,
                 total,
-                file_name: input_path_buf
-                    .file_name()
-                    .unwrap_or_default()
-                    .to_string_lossy()
-                    .to_string(),
+                model_id: model_id.clone(),
             },
         );
 
-        // Determine output path
-        let output_path = if let Some(ref output_dir) = request.output_dir {
-            let file_stem = input_path_buf
-                .file_stem()
-                .ok_or_else(|| "Invalid input file name".to_string())?;
-            PathBuf::from(output_dir).join(format!("{}_no_bg.png", file_stem.to_string_lossy()))
-        } else {
-            let file_stem = input_path_buf
-                .file_stem()
-                .ok_or_else(|| "Invalid input file name".to_string())?;
-            let parent = input_path_buf
-                .parent()
-                .ok_or_else(|| "Invalid input file path".to_string())?;
-            parent.join(format!("{}_no_bg.png", file_stem.to_string_lossy()))
-        };
-
-        // Process the image
-        let result = match processor::process_image(&model_path, &input_path_buf, &output_path) {
-            Ok(_) => ProcessImageResult {
-                input_path: input_path.clone(),
-                output_path: output_path.to_string_lossy().to_string(),
-                success: true,
+        let result = match benchmark::benchmark_model(&model_id, &images, &request.options) {
+            Ok(benchmark) => ModelBenchmarkResult {
+                model_id,
+                benchmark: Some(benchmark),
                 error: None,
             },
-            Err(e) => ProcessImageResult {
-                input_path: input_path.clone(),
-                output_path: String::new(),
-                success: false,
-                error: Some(e.to_string()),
+            Err(e) => ModelBenchmarkResult {
+                model_id,
+                benchmark: None,
+                error: Some(e.into()),
             },
         };
-
         results.push(result);
     }
 
     Ok(results)
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+struct EvaluationRequest {
+    model_id: String,
+    // Images or folders of images to evaluate on
+    image_paths: Vec<String>,
+    ground_truth_dir: String,
+    // Where to save the results as `<model>-evaluation.json` and `.csv`
+    output_dir: Option<String>,
+    #[serde(default)]
+    options: evaluation::EvaluationOptions,
+}
+
+#[tauri::command]
+async fn evaluate_model(
+    request: EvaluationRequest,
+) -> Result<evaluation::Evaluation, CommandError> {
+    let paths: Vec<PathBuf> = request.image_paths.iter().map(PathBuf::from).collect();
+    let images = dataset::collect_images(&paths)?;
+    let evaluation = evaluation::evaluate_model(
+        &request.model_id,
+        &images,
+        Path::new(&request.ground_truth_dir),
+        &request.options,
+    )?;
+
+    if let Some(output_dir) = &request.output_dir {
+        evaluation.save(Path::new(output_dir))?;
+    }
+
+    Ok(evaluation)
+}
+
+// Execution providers this build of ONNX Runtime supports
+#[tauri::command]
+fn get_execution_providers() -> Vec<session::Provider> {
+    session::available_providers()
+}
+
+// Canvas preset commands
+
+#[tauri::command]
+fn get_canvas_presets() -> Result<Vec<canvas::CanvasPreset>, CommandError> {
+    canvas::get_presets().map_err(CommandError::from)
+}
+
+#[tauri::command]
+fn save_canvas_preset(
+    name: String,
+    options: canvas::CanvasOptions,
+) -> Result<canvas::CanvasPreset, CommandError> {
+    canvas::save_preset(&name, options).map_err(CommandError::from)
+}
+
+#[tauri::command]
+fn delete_canvas_preset(name: String) -> Result<(), CommandError> {
+    canvas::delete_preset(&name).map_err(CommandError::from)
+}
+
 #[tauri::command]
-fn check_first_time_setup() -> Result<bool, String> {
+fn check_first_time_setup() -> Result<bool, CommandError> {
     let default_model = models::get_default_model();
     models::is_model_downloaded(&default_model.id)
         .map(|downloaded| !downloaded)
-        .map_err(|e| e.to_string())
+        .map_err(CommandError::from)
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -214,6 +481,12 @@ pub fn run() {
             get_models_dir,
             download_model,
             process_images,
+            get_execution_providers,
+            benchmark_models,
+            evaluate_model,
+            get_canvas_presets,
+            save_canvas_preset,
+            delete_canvas_preset,
             check_first_time_setup,
         ])
         .run(tauri::generate_context!())
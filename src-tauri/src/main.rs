@@ -2,5 +2,9 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 fn main() {
+    if let Some(code) = rmbg_lib::run_cli(std::env::args().skip(1).collect()) {
+        std::process::exit(code);
+    }
+
     rmbg_lib::run()
 }
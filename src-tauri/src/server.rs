@@ -0,0 +1,527 @@
+use anyhow::Result;
+use image::{DynamicImage, ImageError, Rgba};
+use rmbg_core::background;
+use rmbg_core::mask::{self, MaskOperation};
+use rmbg_core::models;
+use rmbg_core::processor::{self, BackgroundRemover, ProcessOptions};
+use rmbg_core::Error;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+// Same defaults as `rembg s`, except we only listen on localhost
+pub const DEFAULT_HOST: &str = "127.0.0.1";
+pub const DEFAULT_PORT: u16 = 7000;
+
+const MAX_HEADER_BYTES: usize = 64 * 1024;
+const MAX_BODY_BYTES: usize = 100 * 1024 * 1024;
+const MAX_HEADERS: usize = 64;
+
+// A client that stalls while sending its request or reading the response
+// gets disconnected rather than holding the connection open
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+const WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Models kept loaded at once. Loading another drops the least recently used
+// one, a request still running on it finishes first.
+const CACHED_SESSIONS: usize = 3;
+
+// Loaded sessions keyed by model id, least recently used first, so each
+// model is only initialized once. Each model has its own lock, so requests
+// for different models run at the same time and the cache lock is only held
+// to look a model up.
+type SessionCache = Arc<Mutex<Vec<(String, Session)>>>;
+type Session = Arc<Mutex<Option<BackgroundRemover>>>;
+
+struct Request {
+    method: String,
+    path: String,
+    query: String,
+    content_type: Option<String>,
+    body: Vec<u8>,
+}
+
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn png(body: Vec<u8>) -> Self {
+        Self {
+            status: 200,
+            content_type: "image/png",
+            body,
+        }
+    }
+
+    fn text(status: u16, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: message.into().into_bytes(),
+        }
+    }
+}
+
+// Parameters understood by rembg's `/api/remove`. Alpha matting options are
+// accepted but ignored since we don't implement matting.
+#[derive(Debug)]
+struct RemoveParams {
+    model: String,
+    only_mask: bool,
+    post_process_mask: bool,
+    background_color: Option<Rgba<u8>>,
+    strip_metadata: bool,
+    keep_all_exif: bool,
+}
+
+impl RemoveParams {
+    fn from_pairs(pairs: &HashMap<String, String>) -> Result<Self, String> {
+        let model = pairs
+            .get("model")
+            .filter(|m| !m.is_empty())
+            .cloned()
+            .unwrap_or_else(|| models::get_default_model().id);
+
+        let only_mask = match pairs.get("om") {
+            Some(value) => {
+                parse_bool(value).ok_or_else(|| format!("Invalid om value: {}", value))?
+            }
+            None => false,
+        };
+
+        let post_process_mask = match pairs.get("ppm") {
+            Some(value) => {
+                parse_bool(value).ok_or_else(|| format!("Invalid ppm value: {}", value))?
+            }
+            None => false,
+        };
+
+        let background_color = match pairs.get("bgc").filter(|c| !c.is_empty()) {
+            Some(value) => Some(
+                background::parse_color(value)
+                    .ok_or_else(|| format!("Invalid bgc value: {}", value))?,
+            ),
+            None => None,
+        };
+
+        let strip_metadata = match pairs.get("strip_metadata") {
+            Some(value) => parse_bool(value)
+                .ok_or_else(|| format!("Invalid strip_metadata value: {}", value))?,
+            None => false,
+        };
+
+        let keep_all_exif = match pairs.get("keep_all_exif") {
+            Some(value) => parse_bool(value)
+                .ok_or_else(|| format!("Invalid keep_all_exif value: {}", value))?,
+            None => false,
+        };
+
+        Ok(Self {
+            model,
+            only_mask,
+            post_process_mask,
+            background_color,
+            strip_metadata,
+            keep_all_exif,
+        })
+    }
+}
+
+pub async fn serve(host: &str, port: u16) -> Result<()> {
+    let listener = TcpListener::bind((host, port)).await?;
+    let sessions: SessionCache = Arc::new(Mutex::new(Vec::new()));
+
+    println!("Listening on http://{}", listener.local_addr()?);
+
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        let sessions = sessions.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, sessions).await {
+                eprintln!("Connection from {} failed: {}", addr, e);
+            }
+        });
+    }
+}
+
+async fn handle_connection(mut stream: TcpStream, sessions: SessionCache) -> Result<()> {
+    let request = tokio::time::timeout(READ_TIMEOUT, read_request(&mut stream))
+        .await
+        .unwrap_or_else(|_| Err(Response::text(408, "Timed out reading the request")));
+    let response = match request {
+        Ok(request) => route(request, sessions).await,
+        Err(response) => response,
+    };
+
+    tokio::time::timeout(WRITE_TIMEOUT, write_response(&mut stream, response))
+        .await
+        .map_err(|_| anyhow::anyhow!("Timed out writing the response"))?
+}
+
+async fn route(request: Request, sessions: SessionCache) -> Response {
+    match (request.method.as_str(), request.path.as_str()) {
+        ("POST", "/api/remove") => remove(request, sessions).await,
+        ("GET", "/api/remove") => Response::text(
+            400,
+            "URL input is not supported, POST the image as multipart or raw body instead",
+        ),
+        (_, "/api/remove") => Response::text(405, "Method not allowed"),
+        _ => Response::text(404, "Not found"),
+    }
+}
+
+async fn remove(request: Request, sessions: SessionCache) -> Response {
+    let mut pairs: HashMap<String, String> = form_urlencoded::parse(request.query.as_bytes())
+        .into_owned()
+        .collect();
+
+    // Multipart uploads carry the image in the `file` field, any other text
+    // fields override query parameters like rembg's form params do
+    let image_bytes = match request.content_type.as_deref().and_then(multipart_boundary) {
+        Some(boundary) => {
+            let mut file = None;
+            for part in parse_multipart(&request.body, &boundary) {
+                if part.name == "file" {
+                    file = Some(part.data.to_vec());
+                } else if part.filename.is_none() {
+                    pairs.insert(part.name, String::from_utf8_lossy(part.data).to_string());
+                }
+            }
+            match file {
+                Some(file) => file,
+                None => return Response::text(400, "Missing multipart field: file"),
+            }
+        }
+        None => request.body,
+    };
+
+    if image_bytes.is_empty() {
+        return Response::text(400, "Missing image data");
+    }
+
+    let params = match RemoveParams::from_pairs(&pairs) {
+        Ok(params) => params,
+        Err(message) => return Response::text(400, message),
+    };
+
+    let model_path = match models::get_model_path(&params.model) {
+        Ok(path) => path,
+        Err(e) => return Response::text(400, e.to_string()),
+    };
+
+    let result = tokio::task::spawn_blocking(move || {
+        process_bytes(&sessions, &params, model_path, &image_bytes)
+    })
+    .await;
+
+    match result {
+        Ok(Ok(png)) => Response::png(png),
+        Ok(Err(e)) => Response::text(error_status(&e), e.to_string()),
+        Err(e) => Response::text(500, e.to_string()),
+    }
+}
+
+// Images that can't be read are the client's problem, anything else is ours.
+// Only decoding reads anything, so an I/O error is a truncated upload.
+fn error_status(error: &anyhow::Error) -> u16 {
+    match error.downcast_ref::<Error>() {
+        Some(Error::Image(ImageError::Unsupported(_))) => 415,
+        Some(Error::Image(ImageError::Decoding(_) | ImageError::IoError(_))) => 400,
+        _ => 500,
+    }
+}
+
+fn process_bytes(
+    sessions: &SessionCache,
+    params: &RemoveParams,
+    model_path: PathBuf,
+    image_bytes: &[u8],
+) -> Result<Vec<u8>> {
+    let (input_image, metadata) = processor::decode_image_with_metadata(image_bytes)?;
+
+    let session = cached_session(sessions, &params.model)?;
+
+    // The model is loaded under its own lock, by the first request for it
+    let mut mask = {
+        let mut session = session
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Session for {} is poisoned", params.model))?;
+        let remover = match session.as_mut() {
+            Some(remover) => remover,
+            None => session.insert(BackgroundRemover::new(&model_path)?),
+        };
+        remover.predict_mask(&input_image)?
+    };
+
+    if params.post_process_mask {
+        mask::apply_operations(&mut mask, &rembg_post_process());
+    }
+
+    // Masks don't get the input's color profile, it only applies to RGB
+    if params.only_mask {
+        return Ok(processor::encode_png(&DynamicImage::ImageLuma8(mask))?);
+    }
+
+    let cutout = BackgroundRemover::apply_mask(&input_image, &mask)?;
+    let result = match params.background_color {
+        Some(color) => processor::apply_background_color(&cutout, color),
+        None => cutout,
+    };
+
+    let options = ProcessOptions {
+        strip_metadata: params.strip_metadata,
+        keep_all_exif: params.keep_all_exif,
+        ..Default::default()
+    };
+    Ok(processor::encode_output(&result, &metadata, &options)?)
+}
+
+// The model's session, marked as the most recently used
+fn cached_session(sessions: &SessionCache, model_id: &str) -> Result<Session> {
+    let mut sessions = sessions
+        .lock()
+        .map_err(|_| anyhow::anyhow!("Session cache is poisoned"))?;
+    let session = match sessions.iter().position(|(id, _)| id == model_id) {
+        Some(index) => sessions.remove(index).1,
+        None => {
+            if sessions.len() >= CACHED_SESSIONS {
+                sessions.remove(0);
+            }
+            Session::default()
+        }
+    };
+    sessions.push((model_id.to_string(), session.clone()));
+    Ok(session)
+}
+
+// rembg's `ppm` smooths the mask with an opening, a blur and a threshold
+fn rembg_post_process() -> [MaskOperation; 4] {
+    [
+        MaskOperation::Erode { radius: 1 },
+        MaskOperation::Dilate { radius: 1 },
+        MaskOperation::Feather { sigma: 2.0 },
+        MaskOperation::Threshold { value: 127 },
+    ]
+}
+
+async fn read_request(stream: &mut TcpStream) -> Result<Request, Response> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 8192];
+
+    // Read until the full header block has arrived
+    let (header_len, (mut request, content_length)) = loop {
+        let read = stream
+            .read(&mut chunk)
+            .await
+            .map_err(|e| Response::text(400, e.to_string()))?;
+        if read == 0 {
+            return Err(Response::text(
+                400,
+                "Connection closed before request was complete",
+            ));
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+
+        let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+        let mut parsed = httparse::Request::new(&mut headers);
+        match parsed.parse(&buffer) {
+            Ok(httparse::Status::Complete(len)) => break (len, parse_head(&parsed)?),
+            Ok(httparse::Status::Partial) if buffer.len() < MAX_HEADER_BYTES => continue,
+            Ok(httparse::Status::Partial) => {
+                return Err(Response::text(431, "Request header fields too large"))
+            }
+            Err(e) => return Err(Response::text(400, e.to_string())),
+        }
+    };
+
+    if content_length > MAX_BODY_BYTES {
+        return Err(Response::text(413, "Payload too large"));
+    }
+
+    let mut body = buffer.split_off(header_len);
+    while body.len() < content_length {
+        let read = stream
+            .read(&mut chunk)
+            .await
+            .map_err(|e| Response::text(400, e.to_string()))?;
+        if read == 0 {
+            return Err(Response::text(
+                400,
+                "Connection closed before body was complete",
+            ));
+        }
+        body.extend_from_slice(&chunk[..read]);
+    }
+    body.truncate(content_length);
+
+    request.body = body;
+    Ok(request)
+}
+
+// Extracts the request line and the headers we care about, returning the
+// expected body length alongside
+fn parse_head(parsed: &httparse::Request) -> Result<(Request, usize), Response> {
+    let method = parsed.method.unwrap_or_default().to_string();
+    let target = parsed.path.unwrap_or_default();
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    let mut content_type = None;
+    let mut content_length = 0;
+
+    for header in parsed.headers.iter() {
+        let value = String::from_utf8_lossy(header.value).trim().to_string();
+        if header.name.eq_ignore_ascii_case("content-type") {
+            content_type = Some(value);
+        } else if header.name.eq_ignore_ascii_case("content-length") {
+            content_length = value
+                .parse()
+                .map_err(|_| Response::text(400, "Invalid Content-Length"))?;
+        } else if header.name.eq_ignore_ascii_case("transfer-encoding") {
+            return Err(Response::text(411, "Chunked requests are not supported"));
+        }
+    }
+
+    Ok((
+        Request {
+            method,
+            path: path.to_string(),
+            query: query.to_string(),
+            content_type,
+            body: Vec::new(),
+        },
+        content_length,
+    ))
+}
+
+async fn write_response(stream: &mut TcpStream, response: Response) -> Result<()> {
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        reason_phrase(response.status),
+        response.content_type,
+        response.body.len()
+    );
+
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(&response.body).await?;
+    stream.flush().await?;
+
+    Ok(())
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        415 => "Unsupported Media Type",
+        431 => "Request Header Fields Too Large",
+        _ => "Internal Server Error",
+    }
+}
+
+struct MultipartPart<'a> {
+    name: String,
+    filename: Option<String>,
+    data: &'a [u8],
+}
+
+fn multipart_boundary(content_type: &str) -> Option<String> {
+    let (mime, params) = content_type.split_once(';')?;
+    if !mime.trim().eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+
+    params.split(';').find_map(|param| {
+        let (key, value) = param.split_once('=')?;
+        key.trim()
+            .eq_ignore_ascii_case("boundary")
+            .then(|| value.trim().trim_matches('"').to_string())
+    })
+}
+
+fn parse_multipart<'a>(body: &'a [u8], boundary: &str) -> Vec<MultipartPart<'a>> {
+    let delimiter = format!("--{}", boundary).into_bytes();
+    let mut parts = Vec::new();
+
+    let Some(mut start) = find_bytes(body, &delimiter, 0) else {
+        return parts;
+    };
+
+    loop {
+        start += delimiter.len();
+
+        // The closing delimiter is followed by `--`
+        if body[start..].starts_with(b"--") {
+            break;
+        }
+        let Some(end) = find_bytes(body, &delimiter, start) else {
+            break;
+        };
+
+        let section = body[start..end]
+            .strip_prefix(b"\r\n")
+            .unwrap_or(&body[start..end]);
+        let section = section.strip_suffix(b"\r\n").unwrap_or(section);
+
+        if let Some(header_end) = find_bytes(section, b"\r\n\r\n", 0) {
+            let headers = String::from_utf8_lossy(&section[..header_end]);
+            let data = &section[header_end + 4..];
+
+            let disposition = headers.lines().find_map(|line| {
+                let (name, value) = line.split_once(':')?;
+                name.trim()
+                    .eq_ignore_ascii_case("content-disposition")
+                    .then(|| value.to_string())
+            });
+
+            if let Some(disposition) = disposition {
+                if let Some(name) = disposition_param(&disposition, "name") {
+                    parts.push(MultipartPart {
+                        name,
+                        filename: disposition_param(&disposition, "filename"),
+                        data,
+                    });
+                }
+            }
+        }
+
+        start = end;
+    }
+
+    parts
+}
+
+fn disposition_param(disposition: &str, key: &str) -> Option<String> {
+    disposition.split(';').find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        (name.trim() == key).then(|| value.trim().trim_matches('"').to_string())
+    })
+}
+
+fn find_bytes(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|pos| pos + from)
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "1" | "yes" | "on" => Some(true),
+        "false" | "0" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
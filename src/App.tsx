@@ -5,16 +5,32 @@ import { openPath } from "@tauri-apps/plugin-opener";
 import { open as openDialog } from "@tauri-apps/plugin-dialog";
 import "./App.css";
 import type {
+  CommandError,
   ModelInfo,
   ModelStatus,
+  Precision,
   ProcessImageResult,
   DownloadProgress,
   ProcessingProgress,
 } from "./types";
 
+function errorMessage(error: unknown): string {
+  if (typeof error === "object" && error !== null && "message" in error) {
+    return (error as CommandError).message;
+  }
+  return String(error);
+}
+
+const PRECISION_LABELS: Record<Precision, string> = {
+  fp32: "Full precision (FP32)",
+  fp16: "Half precision (FP16)",
+  int8: "Quantized (INT8)",
+};
+
 function App() {
   const [models, setModels] = useState<ModelInfo[]>([]);
   const [selectedModelId, setSelectedModelId] = useState<string>("");
+  const [selectedPrecision, setSelectedPrecision] = useState<Precision>("fp32");
   const [modelStatuses, setModelStatuses] = useState<Map<string, ModelStatus>>(
     new Map()
   );
@@ -101,21 +117,21 @@ function App() {
       await refreshModelStatuses(models);
     } catch (error) {
       console.error("Failed to download default model:", error);
-      alert(`Failed to download model: ${error}`);
+      alert(`Failed to download model: ${errorMessage(error)}`);
     } finally {
       setDownloading(false);
       setDownloadProgress(null);
     }
   }
 
-  async function downloadModel(modelId: string) {
+  async function downloadModel(modelId: string, precision: Precision) {
     try {
       setDownloading(true);
-      await invoke("download_model", { modelId });
+      await invoke("download_model", { modelId, precision });
       await refreshModelStatuses(models);
     } catch (error) {
       console.error("Failed to download model:", error);
-      alert(`Failed to download model: ${error}`);
+      alert(`Failed to download model: ${errorMessage(error)}`);
     } finally {
       setDownloading(false);
       setDownloadProgress(null);
@@ -151,7 +167,7 @@ function App() {
     }
 
     const modelStatus = modelStatuses.get(selectedModelId);
-    if (!modelStatus?.downloaded) {
+    if (!modelStatus?.downloaded_precisions.includes(selectedPrecision)) {
       alert("Please download the selected model first");
       return;
     }
@@ -165,6 +181,7 @@ function App() {
           request: {
             image_paths: selectedImages,
             model_id: selectedModelId,
+            precision: selectedPrecision,
             output_dir: null,
           },
         }
@@ -172,7 +189,7 @@ function App() {
       setResults(processResults);
     } catch (error) {
       console.error("Failed to process images:", error);
-      alert(`Failed to process images: ${error}`);
+      alert(`Failed to process images: ${errorMessage(error)}`);
     } finally {
       setProcessing(false);
       setProcessingProgress(null);
@@ -218,6 +235,18 @@ function App() {
 
   const selectedModel = models.find((m) => m.id === selectedModelId);
   const selectedModelStatus = modelStatuses.get(selectedModelId);
+  const selectedPrecisions: Precision[] = [
+    "fp32",
+    ...(selectedModel?.variants.map((v) => v.precision) ?? []),
+  ];
+  const selectedVariantFiles =
+    selectedPrecision === "fp32"
+      ? selectedModel?.files
+      : selectedModel?.variants.find((v) => v.precision === selectedPrecision)
+          ?.files;
+  const selectedVariantDownloaded =
+    selectedModelStatus?.downloaded_precisions.includes(selectedPrecision) ??
+    false;
 
   return (
     <div className="app">
@@ -390,7 +419,10 @@ function App() {
             <h2>Model Selection</h2>
             <select
               value={selectedModelId}
-              onChange={(e) => setSelectedModelId(e.target.value)}
+              onChange={(e) => {
+                setSelectedModelId(e.target.value);
+                setSelectedPrecision("fp32");
+              }}
               className="model-select"
               disabled={processing || downloading}
             >
@@ -402,24 +434,51 @@ function App() {
               ))}
             </select>
 
+            {selectedPrecisions.length > 1 && (
+              <select
+                value={selectedPrecision}
+                onChange={(e) =>
+                  setSelectedPrecision(e.target.value as Precision)
+                }
+                className="model-select"
+                disabled={processing || downloading}
+              >
+                {selectedPrecisions.map((precision) => (
+                  <option key={precision} value={precision}>
+                    {PRECISION_LABELS[precision]}
+                    {selectedModelStatus?.downloaded_precisions.includes(
+                      precision
+                    )
+                      ? " ✓"
+                      : ""}
+                  </option>
+                ))}
+              </select>
+            )}
+
             {selectedModel && (
               <div className="model-info">
                 <p className="model-description">{selectedModel.description}</p>
                 <p className="model-size">
                   Size:{" "}
-                  {selectedModel.files.reduce((sum, f) => sum + f.size_mb, 0)}{" "}
+                  {(selectedVariantFiles ?? []).reduce(
+                    (sum, f) => sum + f.size_mb,
+                    0
+                  )}{" "}
                   MB
                 </p>
-                {selectedModelStatus && !selectedModelStatus.downloaded && (
+                {selectedModelStatus && !selectedVariantDownloaded && (
                   <button
-                    onClick={() => downloadModel(selectedModelId)}
+                    onClick={() =>
+                      downloadModel(selectedModelId, selectedPrecision)
+                    }
                     disabled={downloading || processing}
                     className="button-primary"
                   >
                     Download Model
                   </button>
                 )}
-                {selectedModelStatus?.downloaded && (
+                {selectedVariantDownloaded && (
                   <p className="model-downloaded">✓ Model Downloaded</p>
                 )}
               </div>
@@ -584,7 +643,7 @@ function App() {
                     ) : (
                       <div className="result-error">
                         <p>❌ Failed to process</p>
-                        <p className="error-message">{result.error}</p>
+                        <p className="error-message">{result.error?.message}</p>
                       </div>
                     )}
                   </div>
@@ -9,22 +9,214 @@ export interface ModelInfo {
   name: string;
   description: string;
   files: ModelFile[];
+  variants: ModelVariant[];
   is_default: boolean;
 }
 
+export type Precision = "fp32" | "fp16" | "int8";
+
+export interface ModelVariant {
+  precision: Precision;
+  source: string;
+  files: ModelFile[];
+}
+
 export interface ModelStatus {
   model: ModelInfo;
   downloaded: boolean;
   file_paths: string[];
+  downloaded_precisions: Precision[];
+}
+
+export type ErrorCode =
+  | "model_not_found"
+  | "model_not_downloaded"
+  | "invalid_model"
+  | "unsupported_image_format"
+  | "invalid_image"
+  | "out_of_memory"
+  | "file_not_found"
+  | "permission_denied"
+  | "invalid_path"
+  | "invalid_options"
+  | "preset_not_found"
+  | "invalid_preset_file"
+  | "download_failed"
+  | "inference"
+  | "io"
+  | "internal";
+
+// Error returned by every command and in failed processing results. Besides
+// `code` and `message`, some codes carry extra fields like `model_id`, `path`
+// or `status`.
+export interface CommandError {
+  code: ErrorCode;
+  message: string;
+  model_id?: string | null;
+  path?: string | null;
+  status?: number | null;
+  name?: string;
+}
+
+export type Padding = { pixels: number } | { percent: number };
+
+export interface CropOptions {
+  alpha_threshold?: number;
+  padding?: Padding;
+  // Width divided by height
+  aspect_ratio?: number | null;
+}
+
+export interface CanvasOptions {
+  width?: number;
+  height?: number;
+  fill_ratio?: number;
+  alignment?: "center" | "bottom";
+  // RGBA, transparent when unset
+  background?: [number, number, number, number] | null;
+  alpha_threshold?: number;
+}
+
+export interface CanvasPreset {
+  name: string;
+  options: CanvasOptions;
+  builtin: boolean;
+}
+
+// Mask refinement steps, applied in order
+export type MaskOperation =
+  | { op: "threshold"; value: number }
+  | { op: "erode"; radius: number }
+  | { op: "dilate"; radius: number }
+  | { op: "fill_holes" }
+  | { op: "remove_islands"; min_area: number }
+  | { op: "keep_largest"; count: number }
+  | { op: "feather"; sigma: number };
+
+export type Background = { color: [number, number, number, number] } | { image: string };
+
+export interface DropShadow {
+  offset_x?: number;
+  offset_y?: number;
+  blur?: number;
+  opacity?: number;
+  color?: [number, number, number];
+}
+
+export interface ContactShadow {
+  // Fraction of the subject's height
+  height?: number;
+  blur?: number;
+  opacity?: number;
+  color?: [number, number, number];
+}
+
+export interface Reflection {
+  opacity?: number;
+  // Fraction of the subject's height
+  length?: number;
+  gap?: number;
+}
+
+export type Effect =
+  | ({ type: "drop_shadow" } & DropShadow)
+  | ({ type: "contact_shadow" } & ContactShadow)
+  | ({ type: "reflection" } & Reflection);
+
+export interface BlurOptions {
+  kind?: "gaussian" | "lens";
+  radius?: number;
+  feather?: number;
+}
+
+export type OutputMode =
+  | { mode: "cutout" }
+  | ({ mode: "blurred_background" } & BlurOptions)
+  | { mode: "background_only" };
+
+export interface TileOptions {
+  tile_size?: number;
+  overlap?: number;
+}
+
+export interface GuidedFilterOptions {
+  radius?: number;
+  epsilon?: number;
+}
+
+export type Upsampling =
+  | { method: "lanczos3" }
+  | ({ method: "guided_filter" } & GuidedFilterOptions);
+
+export interface InferenceOptions {
+  tiling?: TileOptions | null;
+  letterbox?: boolean;
+  upsampling?: Upsampling;
+  batch_size?: number;
+}
+
+export type Provider =
+  | "cpu"
+  | "xnnpack"
+  | "openvino"
+  | "cuda"
+  | "tensorrt"
+  | "coreml"
+  | "directml";
+
+export interface SessionOptions {
+  providers?: Provider[];
+  intra_threads?: number | null;
+  inter_threads?: number | null;
+  memory_arena?: boolean;
+  memory_pattern?: boolean;
+}
+
+export interface ProcessOptions {
+  session?: SessionOptions;
+  inference?: InferenceOptions;
+  strip_metadata?: boolean;
+  keep_all_exif?: boolean;
+  mask_operations?: MaskOperation[];
+  invert_mask?: boolean;
+  decontaminate_colors?: boolean;
+  crop?: CropOptions | null;
+  canvas?: CanvasOptions | null;
+  background?: Background | null;
+  effects?: Effect[];
+  output?: OutputMode;
 }
 
 export interface ProcessImageResult {
   input_path: string;
   output_path: string;
   success: boolean;
-  error?: string;
+  error?: CommandError | null;
+  // The model used, the one picked for the image with "auto". Unset for
+  // ensembles.
+  model_id?: string | null;
+  image_kind?: ImageKind | null;
+  execution_provider?: Provider | null;
 }
 
+// How an ensemble combines its models' masks
+export type Fusion = "mean" | "max" | "median" | "confidence";
+
+export interface EnsembleMember {
+  model_id: string;
+  weight?: number;
+}
+
+// Models whose masks are fused for each image, sent as the processing
+// request's `ensemble` in place of its model
+export interface EnsembleOptions {
+  models: EnsembleMember[];
+  fusion?: Fusion;
+}
+
+// What the "auto" model id took an image for
+export type ImageKind = "photo" | "portrait" | "illustration";
+
 export interface DownloadProgress {
   model_id: string;
   file_name: string;
@@ -38,3 +230,64 @@ export interface ProcessingProgress {
   total: number;
   file_name: string;
 }
+
+export interface BenchmarkOptions {
+  precision?: Precision;
+  session?: SessionOptions;
+  inference?: InferenceOptions;
+  ground_truth_dir?: string | null;
+}
+
+export interface ImageBenchmark {
+  path: string;
+  latency_ms: number;
+  iou: number | null;
+}
+
+export interface ModelBenchmark {
+  model_id: string;
+  precision: Precision;
+  provider: Provider;
+  load_time_ms: number;
+  warmup_ms: number;
+  mean_latency_ms: number;
+  median_latency_ms: number;
+  peak_memory_mb: number | null;
+  mean_iou: number | null;
+  images: ImageBenchmark[];
+}
+
+export interface ModelBenchmarkResult {
+  model_id: string;
+  benchmark: ModelBenchmark | null;
+  error?: CommandError | null;
+}
+
+export interface MaskScores {
+  iou: number;
+  f_measure: number;
+  mae: number;
+  boundary_f: number;
+}
+
+export interface EvaluationOptions {
+  precision?: Precision;
+  session?: SessionOptions;
+  inference?: InferenceOptions;
+}
+
+export type ImageEvaluation = { path: string } & MaskScores;
+
+export interface Evaluation {
+  model_id: string;
+  precision: Precision;
+  images: ImageEvaluation[];
+  mean: MaskScores | null;
+  skipped: string[];
+}
+
+export interface BenchmarkProgress {
+  current: number;
+  total: number;
+  model_id: string;
+}